@@ -1,4 +1,16 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/orderbook/order-book-merger.proto")?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    tonic_build::configure()
+        // Served by the gRPC reflection service.
+        .file_descriptor_set_path(out_dir.join("orderbook_descriptor.bin"))
+        // The merged order-books are also published as JSON.
+        .type_attribute(".orderbook.Summary", "#[derive(serde::Serialize)]")
+        .type_attribute(".orderbook.Level", "#[derive(serde::Serialize)]")
+        // The published order-books are not fee-adjusted.
+        .field_attribute(".orderbook.Level.raw_price", "#[serde(skip)]")
+        .compile(&["proto/orderbook/order-book-merger.proto"], &["proto"])?;
     Ok(())
 }
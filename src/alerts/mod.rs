@@ -0,0 +1,613 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::common::http::HttpClient;
+use crate::common::now_ns;
+use crate::merger::MergedBook;
+use crate::proto;
+use crate::state::{ConnectionStatus, ExchangeState, MarketState};
+use crate::OrderBook;
+
+/// How often the rules are evaluated when no merged order-book arrives,
+/// so that stale exchanges are noticed.
+const ALERT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// A post to the webhook fails if it does not respond within this time.
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An alert rule from the configuration file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum AlertRule {
+    /// The merged spread is more than `max_bps` of the mid for longer than `for_secs`.
+    Spread {
+        max_bps: f64,
+        #[serde(default)]
+        for_secs: f64,
+    },
+    /// An exchange's mid is more than `max_bps` from the mean mid of the other exchanges
+    /// for longer than `for_secs`.
+    MidDeviation {
+        max_bps: f64,
+        #[serde(default)]
+        for_secs: f64,
+    },
+    /// An enabled exchange has not sent an order-book for longer than `for_secs`.
+    Stale { for_secs: f64 },
+}
+
+impl AlertRule {
+    /// Check that the thresholds are non-negative numbers.
+    pub fn validate(&self) -> Result<(), String> {
+        let thresholds = match self {
+            AlertRule::Spread { max_bps, for_secs } => [*max_bps, *for_secs],
+            AlertRule::MidDeviation { max_bps, for_secs } => [*max_bps, *for_secs],
+            AlertRule::Stale { for_secs } => [0.0, *for_secs],
+        };
+        if thresholds
+            .iter()
+            .all(|threshold| *threshold >= 0.0 && threshold.is_finite())
+        {
+            Ok(())
+        } else {
+            Err(format!(
+                "The thresholds of {:?} must not be negative.",
+                self
+            ))
+        }
+    }
+}
+
+/// The `[alerts]` settings of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AlertSettings {
+    /// POST each alert as JSON to this URL.
+    pub webhook: Option<String>,
+    pub rules: Vec<AlertRule>,
+}
+
+/// A rule's measurement of the merged order-book or of an exchange.
+struct Check {
+    /// Empty for the merged order-book.
+    exchange: &'static str,
+    value: f64,
+    breached: bool,
+}
+
+/// How long a rule's condition has held, and its alert while firing.
+struct Condition {
+    since: Instant,
+    alert: Option<proto::Alert>,
+}
+
+/// Evaluates the alert rules, and fires and resolves their alerts.
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    /// Exchanges which have never sent an order-book are stale from this time.
+    started: Instant,
+    /// The conditions which are breached or firing.
+    /// `(<rule-index>, <exchange-name>) => <condition>`
+    conditions: HashMap<(usize, &'static str), Condition>,
+}
+
+/// The name of the kind of alert, as its rule is named in the configuration file.
+fn alert_kind_name(kind: proto::AlertKind) -> &'static str {
+    match kind {
+        proto::AlertKind::Spread => "spread",
+        proto::AlertKind::MidDeviation => "mid_deviation",
+        proto::AlertKind::NoOrderBook => "stale",
+    }
+}
+
+fn alert_message(kind: proto::AlertKind, exchange: &str, value: f64, threshold: f64) -> String {
+    match kind {
+        proto::AlertKind::Spread => format!(
+            "The merged spread is {:.1} bps, against a maximum of {} bps.",
+            value, threshold
+        ),
+        proto::AlertKind::MidDeviation => format!(
+            "The mid of {} is {:.1} bps from the other exchanges', against a maximum of {} bps.",
+            exchange, value, threshold
+        ),
+        proto::AlertKind::NoOrderBook => format!(
+            "{} has not sent an order-book for {:.1} seconds, against a maximum of {} seconds.",
+            exchange, value, threshold
+        ),
+    }
+}
+
+/// The mid price of an order-book, if it has bids and asks.
+fn mid(bids: &[proto::Level], asks: &[proto::Level]) -> Option<f64> {
+    Some((bids.first()?.price + asks.first()?.price) / 2.0)
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>, started: Instant) -> Self {
+        Self {
+            rules,
+            started,
+            conditions: HashMap::new(),
+        }
+    }
+
+    /// The alerts which are firing.
+    pub fn firing(&self) -> Vec<proto::Alert> {
+        self.conditions
+            .values()
+            .filter_map(|condition| condition.alert.clone())
+            .collect()
+    }
+
+    /// Evaluate the rules against the merged order-book, the order-books it was merged from,
+    /// and the exchanges' states.
+    /// Returns the alerts which fired or resolved.
+    /// An alert also resolves once its rule can no longer be evaluated, eg. when its exchange is disabled.
+    pub fn evaluate(
+        &mut self,
+        now: Instant,
+        summary: Option<&proto::Summary>,
+        order_books: &HashMap<&'static str, OrderBook>,
+        exchanges: &BTreeMap<&'static str, ExchangeState>,
+    ) -> Vec<proto::Alert> {
+        let mut changes = vec![];
+        let mut evaluated = HashSet::new();
+        for (index, rule) in self.rules.clone().iter().enumerate() {
+            let (kind, threshold, hold, checks) = match *rule {
+                AlertRule::Spread { max_bps, for_secs } => (
+                    proto::AlertKind::Spread,
+                    max_bps,
+                    for_secs,
+                    spread_check(summary, max_bps),
+                ),
+                AlertRule::MidDeviation { max_bps, for_secs } => (
+                    proto::AlertKind::MidDeviation,
+                    max_bps,
+                    for_secs,
+                    mid_deviation_checks(order_books, max_bps),
+                ),
+                // The time without an order-book is already the duration of the condition.
+                AlertRule::Stale { for_secs } => (
+                    proto::AlertKind::NoOrderBook,
+                    for_secs,
+                    0.0,
+                    self.stale_checks(now, exchanges, for_secs),
+                ),
+            };
+            for check in checks {
+                evaluated.insert((index, check.exchange));
+                let alert = |firing| proto::Alert {
+                    kind: kind as i32,
+                    rule: index as u32,
+                    exchange: check.exchange.to_string(),
+                    firing,
+                    timestamp_ns: now_ns() as u64,
+                    value: check.value,
+                    threshold,
+                    message: alert_message(kind, check.exchange, check.value, threshold),
+                };
+                if !check.breached {
+                    if let Some(Condition { alert: Some(_), .. }) =
+                        self.conditions.remove(&(index, check.exchange))
+                    {
+                        changes.push(alert(false));
+                    }
+                    continue;
+                }
+                let condition =
+                    self.conditions
+                        .entry((index, check.exchange))
+                        .or_insert(Condition {
+                            since: now,
+                            alert: None,
+                        });
+                if condition.alert.is_none()
+                    && now.saturating_duration_since(condition.since).as_secs_f64() >= hold
+                {
+                    let fired = alert(true);
+                    changes.push(fired.clone());
+                    condition.alert = Some(fired);
+                }
+            }
+        }
+
+        // Resolve the alerts which were not evaluated.
+        let unevaluated: Vec<_> = self
+            .conditions
+            .keys()
+            .filter(|key| !evaluated.contains(*key))
+            .copied()
+            .collect();
+        for key in unevaluated {
+            if let Some(Condition {
+                alert: Some(alert), ..
+            }) = self.conditions.remove(&key)
+            {
+                changes.push(proto::Alert {
+                    firing: false,
+                    timestamp_ns: now_ns() as u64,
+                    message: format!("No longer evaluated:  {}", alert.message),
+                    ..alert
+                });
+            }
+        }
+        changes
+    }
+
+    /// The time since each enabled exchange's latest order-book.
+    fn stale_checks(
+        &self,
+        now: Instant,
+        exchanges: &BTreeMap<&'static str, ExchangeState>,
+        for_secs: f64,
+    ) -> Vec<Check> {
+        exchanges
+            .iter()
+            .filter(|(_, state)| state.status != ConnectionStatus::Disabled)
+            .map(|(exchange, state)| {
+                let age = now
+                    .saturating_duration_since(state.last_update.unwrap_or(self.started))
+                    .as_secs_f64();
+                Check {
+                    exchange,
+                    value: age,
+                    breached: age > for_secs,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The merged spread in basis points of the mid.
+fn spread_check(summary: Option<&proto::Summary>, max_bps: f64) -> Vec<Check> {
+    match summary.and_then(|summary| Some((summary, mid(&summary.bids, &summary.asks)?))) {
+        Some((summary, mid)) if mid > 0.0 => {
+            let spread_bps = summary.spread / mid * 10_000.0;
+            vec![Check {
+                exchange: "",
+                value: spread_bps,
+                breached: spread_bps > max_bps,
+            }]
+        }
+        _ => vec![],
+    }
+}
+
+/// Each exchange's mid in basis points from the mean mid of the other exchanges.
+fn mid_deviation_checks(
+    order_books: &HashMap<&'static str, OrderBook>,
+    max_bps: f64,
+) -> Vec<Check> {
+    let mids: Vec<(&'static str, f64)> = order_books
+        .values()
+        .filter_map(|order_book| {
+            mid(&order_book.bids, &order_book.asks).map(|mid| (order_book.exchange, mid))
+        })
+        .collect();
+    if mids.len() < 2 {
+        return vec![];
+    }
+    mids.iter()
+        .map(|&(exchange, mid)| {
+            let others = mids
+                .iter()
+                .filter(|(other, _)| *other != exchange)
+                .map(|(_, mid)| mid)
+                .sum::<f64>()
+                / (mids.len() - 1) as f64;
+            let deviation_bps = (mid - others).abs() / others * 10_000.0;
+            Check {
+                exchange,
+                value: deviation_bps,
+                breached: deviation_bps > max_bps,
+            }
+        })
+        .collect()
+}
+
+/// Evaluate the alert rules on every merged order-book, and every [ALERT_CHECK_INTERVAL],
+/// and publish the alerts which fire and resolve.
+pub async fn evaluate_alerts(
+    rules: Vec<AlertRule>,
+    state: Arc<MarketState>,
+    mut merged_order_books: broadcast::Receiver<MergedBook>,
+) {
+    let mut engine = AlertEngine::new(rules, Instant::now());
+    let mut interval = tokio::time::interval(ALERT_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            received = merged_order_books.recv() => match received {
+                Ok(_) | Err(RecvError::Lagged(_)) => { /* Pass */ }
+                Err(RecvError::Closed) => break,
+            },
+            _ = interval.tick() => { /* Pass */ }
+        }
+        let latest = state.latest();
+        let no_order_books = HashMap::new();
+        let changes = engine.evaluate(
+            Instant::now(),
+            latest.as_ref().map(|merged_book| &merged_book.summary),
+            latest.as_ref().map_or(&no_order_books, |merged_book| {
+                merged_book.order_books.as_ref()
+            }),
+            &state.exchanges(),
+        );
+        if changes.is_empty() {
+            continue;
+        }
+        for alert in &changes {
+            if alert.firing {
+                warn!("Alert fired:  {}", alert.message);
+            } else {
+                info!("Alert resolved:  {}", alert.message);
+            }
+        }
+        state.publish_alerts(changes, engine.firing());
+    }
+}
+
+/// The alerts which a client has been sent as firing,
+/// so that each alert fires and resolves once, even if it is both in the firing alerts
+/// a stream starts with and among the changes published after it subscribed.
+#[derive(Default)]
+pub struct SentAlerts {
+    /// `(<rule-index>, <exchange-name>)`
+    firing: HashSet<(u32, String)>,
+}
+
+impl SentAlerts {
+    /// Whether the alert changes what the client has been sent, and should be sent.
+    pub fn is_change(&mut self, alert: &proto::Alert) -> bool {
+        let key = (alert.rule, alert.exchange.clone());
+        if alert.firing {
+            self.firing.insert(key)
+        } else {
+            self.firing.remove(&key)
+        }
+    }
+}
+
+/// The JSON body of an alert posted to the webhook.
+fn webhook_body(alert: &proto::Alert) -> String {
+    let kind = proto::AlertKind::from_i32(alert.kind).unwrap_or(proto::AlertKind::Spread);
+    serde_json::json!({
+        "kind": alert_kind_name(kind),
+        "rule": alert.rule,
+        "exchange": alert.exchange,
+        "firing": alert.firing,
+        "timestamp_ns": alert.timestamp_ns,
+        "value": alert.value,
+        "threshold": alert.threshold,
+        "message": alert.message,
+    })
+    .to_string()
+}
+
+/// POST each alert to the webhook.  Failed posts are logged, not retried.
+pub async fn send_to_webhook(
+    url: String,
+    http: Box<dyn HttpClient>,
+    mut alerts: broadcast::Receiver<proto::Alert>,
+) {
+    loop {
+        let alert = match alerts.recv().await {
+            Ok(alert) => alert,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("The alert webhook missed {} alerts.", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        match http.post_json(&url, webhook_body(&alert)).await {
+            Ok(response) if response.is_success() => { /* Pass */ }
+            Ok(response) => warn!(
+                "The alert webhook {} responded with {}:  {}",
+                url, response.status, response.body
+            ),
+            Err(err) => warn!("Failed to post an alert to {}:  {}", url, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::{Duration, Instant};
+
+    use super::{webhook_body, AlertEngine, AlertRule, SentAlerts};
+    use crate::common::fixtures::top_of_book;
+    use crate::proto;
+    use crate::state::{ConnectionStatus, MarketState};
+
+    fn summary(bid: f64, ask: f64) -> proto::Summary {
+        let book = top_of_book("a", bid, ask);
+        proto::Summary {
+            spread: ask - bid,
+            bids: book.bids,
+            asks: book.asks,
+        }
+    }
+
+    #[test]
+    fn fire_and_resolve_spread_alerts() {
+        let t0 = Instant::now();
+        let secs = |secs| t0 + Duration::from_secs(secs);
+        let mut engine = AlertEngine::new(
+            vec![AlertRule::Spread {
+                max_bps: 50.0,
+                for_secs: 5.0,
+            }],
+            t0,
+        );
+        let none = HashMap::new();
+        let no_exchanges = BTreeMap::new();
+        let wide = summary(99.0, 101.0);
+
+        assert!(engine
+            .evaluate(secs(0), Some(&wide), &none, &no_exchanges)
+            .is_empty());
+        assert!(engine
+            .evaluate(secs(4), Some(&wide), &none, &no_exchanges)
+            .is_empty());
+        let fired = engine.evaluate(secs(5), Some(&wide), &none, &no_exchanges);
+        assert_eq!(1, fired.len());
+        assert!(fired[0].firing);
+        assert_eq!(200.0, fired[0].value);
+        // The firing alert is the one which was published.
+        assert_eq!(fired, engine.firing());
+        // It fires once.
+        assert!(engine
+            .evaluate(secs(6), Some(&wide), &none, &no_exchanges)
+            .is_empty());
+
+        let resolved = engine.evaluate(secs(7), Some(&summary(99.9, 100.1)), &none, &no_exchanges);
+        assert_eq!(1, resolved.len());
+        assert!(!resolved[0].firing);
+        assert!(engine.firing().is_empty());
+
+        // Narrowing restarts the duration.
+        engine.evaluate(secs(8), Some(&wide), &none, &no_exchanges);
+        assert!(engine
+            .evaluate(secs(12), Some(&wide), &none, &no_exchanges)
+            .is_empty());
+    }
+
+    #[test]
+    fn send_each_change_once() {
+        let t0 = Instant::now();
+        let mut engine = AlertEngine::new(
+            vec![AlertRule::Spread {
+                max_bps: 50.0,
+                for_secs: 0.0,
+            }],
+            t0,
+        );
+        let none = HashMap::new();
+        let no_exchanges = BTreeMap::new();
+        let fired = engine.evaluate(t0, Some(&summary(99.0, 101.0)), &none, &no_exchanges);
+        let resolved = engine.evaluate(t0, Some(&summary(99.9, 100.1)), &none, &no_exchanges);
+
+        let mut sent_alerts = SentAlerts::default();
+        // A stream starts with the firing alert, which was also published after it subscribed.
+        assert!(sent_alerts.is_change(&fired[0]));
+        assert!(!sent_alerts.is_change(&fired[0]));
+        assert!(sent_alerts.is_change(&resolved[0]));
+        assert!(!sent_alerts.is_change(&resolved[0]));
+        // An alert which resolved before the stream started is not sent.
+        assert!(!SentAlerts::default().is_change(&resolved[0]));
+    }
+
+    #[test]
+    fn fire_rules_of_the_same_kind_apart() {
+        let t0 = Instant::now();
+        let mut engine = AlertEngine::new(
+            vec![
+                AlertRule::Spread {
+                    max_bps: 20.0,
+                    for_secs: 0.0,
+                },
+                AlertRule::Spread {
+                    max_bps: 50.0,
+                    for_secs: 0.0,
+                },
+            ],
+            t0,
+        );
+        let none = HashMap::new();
+        let no_exchanges = BTreeMap::new();
+        let rules = |alerts: &[proto::Alert]| -> Vec<(u32, bool)> {
+            let mut rules: Vec<_> = alerts
+                .iter()
+                .map(|alert| (alert.rule, alert.firing))
+                .collect();
+            rules.sort();
+            rules
+        };
+        let mut sent_alerts = SentAlerts::default();
+
+        // 30 bps breaches the first rule only.
+        let fired = engine.evaluate(t0, Some(&summary(99.85, 100.15)), &none, &no_exchanges);
+        assert_eq!(vec![(0, true)], rules(&fired));
+        assert!(sent_alerts.is_change(&fired[0]));
+        // 200 bps breaches both.
+        let fired = engine.evaluate(t0, Some(&summary(99.0, 101.0)), &none, &no_exchanges);
+        assert_eq!(vec![(1, true)], rules(&fired));
+        assert!(sent_alerts.is_change(&fired[0]));
+        assert_eq!(vec![(0, true), (1, true)], rules(&engine.firing()));
+        // 30 bps resolves the second rule only.
+        let resolved = engine.evaluate(t0, Some(&summary(99.85, 100.15)), &none, &no_exchanges);
+        assert_eq!(vec![(1, false)], rules(&resolved));
+        assert!(sent_alerts.is_change(&resolved[0]));
+        assert_eq!(vec![(0, true)], rules(&engine.firing()));
+        let resolved = engine.evaluate(t0, Some(&summary(99.9, 100.0)), &none, &no_exchanges);
+        assert_eq!(vec![(0, false)], rules(&resolved));
+        assert!(sent_alerts.is_change(&resolved[0]));
+    }
+
+    #[test]
+    fn fire_exchange_alerts() {
+        let t0 = Instant::now();
+        let mut engine = AlertEngine::new(
+            vec![
+                AlertRule::MidDeviation {
+                    max_bps: 50.0,
+                    for_secs: 0.0,
+                },
+                AlertRule::Stale { for_secs: 10.0 },
+            ],
+            t0,
+        );
+        let order_books = HashMap::from([
+            ("a", top_of_book("a", 99.0, 101.0)),
+            ("b", top_of_book("b", 100.0, 102.0)),
+            ("c", top_of_book("c", 99.0, 101.0)),
+        ]);
+        let state = MarketState::default();
+        state.book_updated("a");
+        state.set_status("b", ConnectionStatus::Connecting, "Connecting.", None);
+        state.set_status("c", ConnectionStatus::Disabled, "Disabled.", None);
+
+        let fired = engine.evaluate(
+            t0 + Duration::from_secs(11),
+            None,
+            &order_books,
+            &state.exchanges(),
+        );
+        let mut alerts: Vec<_> = fired
+            .iter()
+            .map(|alert| (alert.kind, alert.exchange.as_str()))
+            .collect();
+        alerts.sort();
+        // b is 100 bps from a and c, which are 50 bps from the others.
+        // a and b have not sent an order-book for 11 seconds, and c is disabled.
+        assert_eq!(
+            vec![
+                (proto::AlertKind::MidDeviation as i32, "b"),
+                (proto::AlertKind::NoOrderBook as i32, "a"),
+                (proto::AlertKind::NoOrderBook as i32, "b"),
+            ],
+            alerts
+        );
+
+        // Without the order-books, the deviation alert is no longer evaluated.
+        let resolved = engine.evaluate(
+            t0 + Duration::from_secs(12),
+            None,
+            &HashMap::new(),
+            &state.exchanges(),
+        );
+        assert_eq!(1, resolved.len());
+        assert_eq!("b", resolved[0].exchange);
+        assert!(!resolved[0].firing);
+
+        let body: serde_json::Value = serde_json::from_str(&webhook_body(&fired[0])).unwrap();
+        assert_eq!(true, body["firing"]);
+        assert!(body["kind"].is_string());
+        assert_eq!(fired[0].rule, body["rule"]);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::proto;
+
+/// The distances from the mid price, in basis points, of the cumulative depth.
+pub const DEPTH_BANDS_BPS: [f64; 4] = [10.0, 25.0, 50.0, 100.0];
+
+/// The volume-weighted average price and the total volume of the levels.
+fn vwap(levels: &[proto::Level]) -> (f64, f64) {
+    let volume: f64 = levels.iter().map(|level| level.amount).sum();
+    let notional: f64 = levels.iter().map(|level| level.price * level.amount).sum();
+    match volume {
+        0.0 => (levels[0].price, 0.0),
+        _ => (notional / volume, volume),
+    }
+}
+
+/// The mid of the bids' and asks' average prices, each weighted by the other side's volume,
+/// so that it leans towards the side with less volume, which is more likely to be taken.
+fn weighted_mid(bids: &[proto::Level], asks: &[proto::Level]) -> f64 {
+    let (bid_price, bid_volume) = vwap(bids);
+    let (ask_price, ask_volume) = vwap(asks);
+    match bid_volume + ask_volume {
+        0.0 => (bid_price + ask_price) / 2.0,
+        volume => (bid_price * ask_volume + ask_price * bid_volume) / volume,
+    }
+}
+
+/// Analyse an order-book, whose bids and asks are best price first.
+/// `levels` is the number of levels of each side of the weighted mid and the imbalance.
+/// Returns `None` if either side is empty, since there is no mid price.
+pub fn analyze(
+    exchange: &str,
+    bids: &[proto::Level],
+    asks: &[proto::Level],
+    levels: usize,
+) -> Option<proto::OrderBookAnalytics> {
+    let mid = (bids.first()?.price + asks.first()?.price) / 2.0;
+    let top_bids = &bids[..levels.clamp(1, bids.len())];
+    let top_asks = &asks[..levels.clamp(1, asks.len())];
+    let (_, bid_volume) = vwap(top_bids);
+    let (_, ask_volume) = vwap(top_asks);
+    let imbalance = match bid_volume + ask_volume {
+        0.0 => 0.0,
+        volume => (bid_volume - ask_volume) / volume,
+    };
+    let depth = DEPTH_BANDS_BPS
+        .iter()
+        .map(|&bps| {
+            let distance = mid * bps / 10_000.0;
+            proto::DepthBand {
+                bps,
+                bid_amount: bids
+                    .iter()
+                    .filter(|level| level.price >= mid - distance)
+                    .map(|level| level.amount)
+                    .sum(),
+                ask_amount: asks
+                    .iter()
+                    .filter(|level| level.price <= mid + distance)
+                    .map(|level| level.amount)
+                    .sum(),
+            }
+        })
+        .collect();
+
+    Some(proto::OrderBookAnalytics {
+        exchange: exchange.to_string(),
+        mid,
+        microprice: weighted_mid(&bids[..1], &asks[..1]),
+        weighted_mid: weighted_mid(top_bids, top_asks),
+        imbalance,
+        depth,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::analyze;
+    use crate::common::fixtures::level;
+
+    #[test]
+    fn analyze_order_book() {
+        let bids = [level("a", 99.0, 1.0), level("a", 98.0, 3.0)];
+        let asks = [level("a", 101.0, 3.0), level("a", 103.0, 1.0)];
+
+        let analytics = analyze("a", &bids, &asks, 1).unwrap();
+        assert_eq!(100.0, analytics.mid);
+        // The larger ask leans the price towards the bid.
+        assert_eq!(99.5, analytics.microprice);
+        assert_eq!(99.5, analytics.weighted_mid);
+        assert_eq!(-0.5, analytics.imbalance);
+        let depth: Vec<_> = analytics
+            .depth
+            .iter()
+            .map(|band| (band.bps, band.bid_amount, band.ask_amount))
+            .collect();
+        assert_eq!(
+            vec![
+                (10.0, 0.0, 0.0),
+                (25.0, 0.0, 0.0),
+                (50.0, 0.0, 0.0),
+                (100.0, 1.0, 3.0)
+            ],
+            depth
+        );
+
+        let analytics = analyze("a", &bids, &asks, 5).unwrap();
+        assert_eq!(99.5, analytics.microprice);
+        assert_eq!((98.25 + 101.5) / 2.0, analytics.weighted_mid);
+        assert_eq!(0.0, analytics.imbalance);
+
+        assert_eq!(None, analyze("a", &bids, &[], 5));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::proto;
+use crate::routing::ExchangeTerms;
+use crate::OrderBook;
+
+/// Find the exchange pairs where one exchange's bids are above another's asks.
+/// Each opportunity buys every crossed ask of one exchange and sells to every crossed bid of the other.
+/// The most profitable after fees come first.
+pub fn find_opportunities(
+    order_books: &HashMap<&'static str, OrderBook>,
+    terms: &HashMap<String, ExchangeTerms>,
+) -> Vec<proto::ArbitrageOpportunity> {
+    let terms_of = |exchange: &str| terms.get(exchange).copied().unwrap_or_default();
+    order_books
+        .values()
+        .sorted_by_key(|order_book| order_book.exchange)
+        .permutations(2)
+        .filter_map(|pair| {
+            let (buy, sell) = (pair[0], pair[1]);
+            opportunity(buy, sell, terms_of(buy.exchange), terms_of(sell.exchange))
+        })
+        .sorted_by(|a, b| b.net_profit.partial_cmp(&a.net_profit).unwrap())
+        .collect()
+}
+
+/// Buy the asks of one exchange while they are below the bids of the other.
+fn opportunity(
+    buy: &OrderBook,
+    sell: &OrderBook,
+    buy_terms: ExchangeTerms,
+    sell_terms: ExchangeTerms,
+) -> Option<proto::ArbitrageOpportunity> {
+    let mut asks = buy.asks.iter().map(|level| (level.price, level.amount));
+    let mut bids = sell.bids.iter().map(|level| (level.price, level.amount));
+    let (mut ask, mut bid) = (asks.next()?, bids.next()?);
+
+    let mut quantity = 0.0;
+    let mut cost = 0.0;
+    let mut proceeds = 0.0;
+    let mut net_cost = 0.0;
+    let mut net_proceeds = 0.0;
+    while ask.0 < bid.0 {
+        let amount = ask.1.min(bid.1);
+        quantity += amount;
+        cost += ask.0 * amount;
+        proceeds += bid.0 * amount;
+        net_cost += buy_terms.fee_adjusted_price(proto::Side::Buy, ask.0) * amount;
+        net_proceeds += sell_terms.fee_adjusted_price(proto::Side::Sell, bid.0) * amount;
+
+        ask.1 -= amount;
+        bid.1 -= amount;
+        if ask.1 <= 0.0 {
+            match asks.next() {
+                Some(next) => ask = next,
+                None => break,
+            }
+        }
+        if bid.1 <= 0.0 {
+            match bids.next() {
+                Some(next) => bid = next,
+                None => break,
+            }
+        }
+    }
+    if quantity <= 0.0 {
+        return None;
+    }
+
+    Some(proto::ArbitrageOpportunity {
+        buy_exchange: buy.exchange.to_string(),
+        sell_exchange: sell.exchange.to_string(),
+        quantity,
+        buy_price: cost / quantity,
+        sell_price: proceeds / quantity,
+        gross_profit: proceeds - cost,
+        net_profit: net_proceeds - net_cost,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::find_opportunities;
+    use crate::common::fixtures::order_book;
+    use crate::routing::ExchangeTerms;
+
+    #[test]
+    fn find_crossed_books() {
+        let order_books = HashMap::from([
+            (
+                "a",
+                order_book("a", &[(99.0, 1.0)], &[(100.0, 1.0), (101.0, 2.0)]),
+            ),
+            (
+                "b",
+                order_book("b", &[(102.0, 1.5), (100.5, 5.0)], &[(103.0, 1.0)]),
+            ),
+        ]);
+        let terms = HashMap::from([(
+            "b".to_string(),
+            ExchangeTerms {
+                taker_fee_bps: 100.0,
+                min_order_size: 0.0,
+                synthetic: false,
+            },
+        )]);
+
+        let opportunities = find_opportunities(&order_books, &terms);
+        assert_eq!(1, opportunities.len());
+        let opportunity = &opportunities[0];
+        assert_eq!(
+            ("a", "b"),
+            (&*opportunity.buy_exchange, &*opportunity.sell_exchange)
+        );
+        // 1 at 100 and 0.5 at 101 sold at 102, then none of 101 is below 100.5.
+        assert_eq!(1.5, opportunity.quantity);
+        assert_eq!(150.5 / 1.5, opportunity.buy_price);
+        assert_eq!(102.0, opportunity.sell_price);
+        assert_eq!(2.5, opportunity.gross_profit);
+        assert!((opportunity.net_profit - (2.5 - 1.53)).abs() < 1e-9);
+
+        // Uncrossed.
+        let order_books = HashMap::from([
+            ("a", order_book("a", &[(99.0, 1.0)], &[(100.0, 1.0)])),
+            ("b", order_book("b", &[(99.5, 1.0)], &[(100.5, 1.0)])),
+        ]);
+        assert!(find_opportunities(&order_books, &terms).is_empty());
+    }
+}
@@ -1,12 +1,115 @@
+use std::collections::HashMap;
+use std::fs;
 use std::net::IpAddr;
+use std::path::PathBuf;
 
-use clap::{crate_name, crate_version, value_t_or_exit, Arg};
+use clap::{
+    crate_name, crate_version, value_t_or_exit, values_t_or_exit, AppSettings, Arg, SubCommand,
+};
+use serde::Deserialize;
+
+use crate::alerts::AlertSettings;
+use crate::common::instrument::Instrument;
+use crate::exchange::{Exchange, SymbolFormat};
+use crate::replay::ReplaySpeed;
+use crate::routing::ExchangeTerms;
+use crate::rpc::auth::Credentials;
+use crate::rpc::limits::StreamLimits;
+use crate::rpc::tls::TlsConfig;
+use crate::synthetic::SyntheticInstrument;
 
 pub struct Config {
-    pub symbol: String,
+    pub symbol: Instrument,
+    /// The exchanges to read order-books from, by registry name.
+    pub exchanges: Vec<String>,
     pub host: IpAddr,
     pub port: u16,
+    /// Serve gRPC over TLS.
+    pub tls: Option<TlsConfig>,
+    /// Require gRPC clients to authenticate with one of these tokens.
+    pub credentials: Option<Credentials>,
+    pub stream_limits: StreamLimits,
+    /// Port number of the optional websocket JSON service.
+    pub ws_port: Option<u16>,
+    /// Port number of the optional HTTP service.
+    pub http_port: Option<u16>,
+    /// The number of live exchanges required to report ready.
+    pub min_live_exchanges: usize,
     pub log_level: log::LevelFilter,
+    /// Record the raw exchange frames to this directory.
+    pub record_dir: Option<PathBuf>,
+    /// Record the merged order-books to this directory.
+    pub history_dir: Option<PathBuf>,
+    pub mode: Mode,
+    pub settings: Settings,
+}
+
+/// Where the exchange order-books come from.
+pub enum Mode {
+    /// The exchanges' websocket streams.
+    Live,
+    /// Previously recorded frames.
+    Replay {
+        /// Recording files, or directories of them.
+        paths: Vec<PathBuf>,
+        speed: ReplaySpeed,
+    },
+}
+
+/// Settings read from the optional TOML configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// `<exchange-name> => <exchange-settings>`
+    pub exchanges: HashMap<String, ExchangeSettings>,
+    pub alerts: AlertSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExchangeSettings {
+    /// Read from the exchange in addition to `--exchanges` if true, or not at all if false.
+    pub enabled: Option<bool>,
+    /// Overrides of the exchange's symbol for an instrument.
+    /// `<canonical-symbol> => <exchange-symbol>`, eg. `"ETH/BTC" = "ethbtc"`
+    pub symbols: HashMap<String, String>,
+    /// Overrides the exchange adapter's symbol format, eg. `"upper_concat"`.
+    pub symbol_format: Option<SymbolFormat>,
+    /// Base URL of the exchange's REST API, eg. to point it at a local stand-in.
+    pub rest_endpoint: Option<String>,
+    /// The fee of taking liquidity, in basis points of the traded amount.
+    pub taker_fee_bps: f64,
+    /// The minimum size of an order, in the base currency.
+    pub min_order_size: f64,
+    /// Instruments which the exchange does not list, built from two instruments which it does.
+    /// `<canonical-symbol> => [<canonical-symbol>, <canonical-symbol>]`,
+    /// eg. `"ETH/BTC" = ["ETH/USDT", "BTC/USDT"]`
+    pub synthetic: HashMap<String, [String; 2]>,
+}
+
+impl ExchangeSettings {
+    /// The synthetic instrument configured for the symbol, if there is one.
+    fn synthetic_instrument(
+        &self,
+        symbol: &Instrument,
+    ) -> Option<Result<SyntheticInstrument, String>> {
+        self.synthetic
+            .iter()
+            .find_map(|(canonical, legs)| match canonical.parse::<Instrument>() {
+                Ok(instrument) if instrument == *symbol => Some(parse_synthetic(instrument, legs)),
+                _ => None,
+            })
+    }
+}
+
+fn parse_synthetic(
+    instrument: Instrument,
+    [first, second]: &[String; 2],
+) -> Result<SyntheticInstrument, String> {
+    SyntheticInstrument::new(
+        instrument,
+        [first.parse::<Instrument>()?, second.parse::<Instrument>()?],
+    )
 }
 
 impl Config {
@@ -17,6 +120,7 @@ impl Config {
             .author("Dan Aharon <dan@aharon.dev>")
             .arg(
                 Arg::with_name("log-level")
+                    .global(true)
                     .short("l")
                     .long("log-level")
                     .help("Log level (TRACE, DEBUG, ERROR, WARN, INFO).")
@@ -26,6 +130,7 @@ impl Config {
             )
             .arg(
                 Arg::with_name("host")
+                    .global(true)
                     .short("h")
                     .long("host")
                     .help("IP address to listen on")
@@ -35,6 +140,7 @@ impl Config {
             )
             .arg(
                 Arg::with_name("port")
+                    .global(true)
                     .short("p")
                     .long("port")
                     .help("Port number to listen on")
@@ -42,23 +148,409 @@ impl Config {
                     .value_name("PORT")
                     .default_value("8080"),
             )
+            .arg(
+                Arg::with_name("tls-cert")
+                    .global(true)
+                    .long("tls-cert")
+                    .help("PEM certificate chain to serve gRPC over TLS with")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .requires("tls-key"),
+            )
+            .arg(
+                Arg::with_name("tls-key")
+                    .global(true)
+                    .long("tls-key")
+                    .help("PEM private key of the TLS certificate")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .requires("tls-cert"),
+            )
+            .arg(
+                Arg::with_name("tls-client-ca")
+                    .global(true)
+                    .long("tls-client-ca")
+                    .help("PEM CA certificates which gRPC client certificates must be signed by")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .requires("tls-cert"),
+            )
+            .arg(
+                Arg::with_name("credentials")
+                    .global(true)
+                    .long("credentials")
+                    .help("TOML file of the gRPC clients' bearer tokens and entitlements")
+                    .takes_value(true)
+                    .value_name("FILE"),
+            )
+            .arg(
+                Arg::with_name("max-streams")
+                    .global(true)
+                    .long("max-streams")
+                    .help("The maximum number of concurrent gRPC streams")
+                    .takes_value(true)
+                    .value_name("COUNT"),
+            )
+            .arg(
+                Arg::with_name("max-streams-per-peer")
+                    .global(true)
+                    .long("max-streams-per-peer")
+                    .help("The maximum number of concurrent gRPC streams from each IP address")
+                    .takes_value(true)
+                    .value_name("COUNT"),
+            )
+            .arg(
+                Arg::with_name("new-stream-rate")
+                    .global(true)
+                    .long("new-stream-rate")
+                    .help("The maximum number of new gRPC streams per second from each IP address")
+                    .takes_value(true)
+                    .value_name("RATE"),
+            )
+            .arg(
+                Arg::with_name("ws-port")
+                    .global(true)
+                    .long("ws-port")
+                    .help("Port number to serve the merged order-books as JSON over websockets on")
+                    .takes_value(true)
+                    .value_name("PORT"),
+            )
+            .arg(
+                Arg::with_name("http-port")
+                    .global(true)
+                    .long("http-port")
+                    .help("Port number to serve the HTTP API on")
+                    .takes_value(true)
+                    .value_name("PORT"),
+            )
+            .arg(
+                Arg::with_name("min-live-exchanges")
+                    .global(true)
+                    .long("min-live-exchanges")
+                    .help("The number of live exchanges required to report ready")
+                    .takes_value(true)
+                    .value_name("COUNT")
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name("exchanges")
+                    .global(true)
+                    .short("e")
+                    .long("exchanges")
+                    .help("Comma-separated list of exchanges to read from")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .value_name("EXCHANGES")
+                    .default_value("binance,bitstamp"),
+            )
+            .arg(
+                Arg::with_name("config")
+                    .global(true)
+                    .short("c")
+                    .long("config")
+                    .help("Path to a TOML configuration file")
+                    .takes_value(true)
+                    .value_name("FILE"),
+            )
+            .arg(
+                Arg::with_name("record-dir")
+                    .short("r")
+                    .long("record-dir")
+                    .help("Record the raw exchange frames to compressed files in this directory")
+                    .takes_value(true)
+                    .value_name("DIR"),
+            )
+            .arg(
+                Arg::with_name("history-dir")
+                    .long("history-dir")
+                    .global(true)
+                    .help("Record the merged order-books to Arrow IPC files in this directory")
+                    .takes_value(true)
+                    .value_name("DIR"),
+            )
             .arg(
                 Arg::with_name("SYMBOL")
-                    .help("The trading symbol, eg. 'ethbtc'")
+                    .help("The trading symbol, eg. 'ethbtc' or 'ETH/BTC'")
                     .required(true),
             )
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .subcommand(
+                SubCommand::with_name("replay")
+                    .about("Merge recorded exchange frames instead of the live streams")
+                    .arg(
+                        Arg::with_name("speed")
+                            .short("s")
+                            .long("speed")
+                            .help("Replay speed as a multiple of the original speed [default: 1]")
+                            .takes_value(true)
+                            .value_name("MULTIPLE")
+                            .conflicts_with("max-speed"),
+                    )
+                    .arg(
+                        Arg::with_name("max-speed")
+                            .long("max-speed")
+                            .help("Replay as fast as possible"),
+                    )
+                    .arg(
+                        Arg::with_name("SYMBOL")
+                            .help("The trading symbol to replay, eg. 'ethbtc' or 'ETH/BTC'")
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("PATH")
+                            .help("Recording files, or directories of recording files")
+                            .required(true)
+                            .multiple(true),
+                    ),
+            )
             .get_matches();
 
-        let symbol = value_t_or_exit!(matches.value_of("SYMBOL"), String);
+        let record_dir = matches.value_of("record-dir").map(PathBuf::from);
+        let (matches, mode) = match matches.subcommand() {
+            ("replay", Some(replay_matches)) => {
+                let speed = if replay_matches.is_present("max-speed") {
+                    ReplaySpeed::AsFastAsPossible
+                } else if replay_matches.is_present("speed") {
+                    let multiple = value_t_or_exit!(replay_matches.value_of("speed"), f64);
+                    if multiple <= 0.0 {
+                        clap::Error::with_description(
+                            "The replay speed must be positive.",
+                            clap::ErrorKind::InvalidValue,
+                        )
+                        .exit()
+                    }
+                    ReplaySpeed::Multiple(multiple)
+                } else {
+                    ReplaySpeed::Multiple(1.0)
+                };
+                let paths = values_t_or_exit!(replay_matches.values_of("PATH"), PathBuf);
+                (replay_matches, Mode::Replay { paths, speed })
+            }
+            _ => (&matches, Mode::Live),
+        };
+
+        let symbol = value_t_or_exit!(matches.value_of("SYMBOL"), Instrument);
+        let exchanges = values_t_or_exit!(matches.values_of("exchanges"), String);
         let host = value_t_or_exit!(matches.value_of("host"), IpAddr);
         let port = value_t_or_exit!(matches.value_of("port"), u16);
+        let tls = matches.value_of("tls-cert").map(|cert| TlsConfig {
+            cert: PathBuf::from(cert),
+            key: PathBuf::from(matches.value_of("tls-key").unwrap()),
+            client_ca: matches.value_of("tls-client-ca").map(PathBuf::from),
+        });
+        let credentials = matches.value_of("credentials").map(|path| {
+            Credentials::load(path).unwrap_or_else(|err| {
+                clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit()
+            })
+        });
+        let stream_limits = StreamLimits {
+            max_streams: matches
+                .value_of("max-streams")
+                .map(|_| value_t_or_exit!(matches.value_of("max-streams"), usize)),
+            max_streams_per_peer: matches
+                .value_of("max-streams-per-peer")
+                .map(|_| value_t_or_exit!(matches.value_of("max-streams-per-peer"), usize)),
+            new_stream_rate: matches
+                .value_of("new-stream-rate")
+                .map(|_| value_t_or_exit!(matches.value_of("new-stream-rate"), f64)),
+        };
+        if stream_limits
+            .new_stream_rate
+            .is_some_and(|rate| rate <= 0.0 || !rate.is_finite())
+        {
+            clap::Error::with_description(
+                "The new stream rate must be positive.",
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit()
+        }
+        let ws_port = matches
+            .value_of("ws-port")
+            .map(|_| value_t_or_exit!(matches.value_of("ws-port"), u16));
+        let http_port = matches
+            .value_of("http-port")
+            .map(|_| value_t_or_exit!(matches.value_of("http-port"), u16));
+        let min_live_exchanges = value_t_or_exit!(matches.value_of("min-live-exchanges"), usize);
         let log_level = value_t_or_exit!(matches.value_of("log-level"), log::LevelFilter);
+        let history_dir = matches.value_of("history-dir").map(PathBuf::from);
+        let settings = match matches.value_of("config") {
+            None => Settings::default(),
+            Some(path) => Settings::load(path).unwrap_or_else(|err| {
+                clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit()
+            }),
+        };
+
+        let exchanges = settings.enabled_exchanges(exchanges);
 
         Self {
             symbol,
+            exchanges,
             host,
             port,
+            tls,
+            credentials,
+            stream_limits,
+            ws_port,
+            http_port,
+            min_live_exchanges,
             log_level,
+            record_dir,
+            history_dir,
+            mode,
+            settings,
+        }
+    }
+
+    /// The symbol to subscribe to on the given exchange.
+    pub fn exchange_symbol(&self, exchange: &dyn Exchange) -> String {
+        self.instrument_symbol(exchange, &self.symbol)
+    }
+
+    /// The exchange-specific symbol of an instrument.
+    /// A symbol override from the configuration file takes precedence
+    /// over the configured symbol format, which takes precedence over the exchange's.
+    pub fn instrument_symbol(&self, exchange: &dyn Exchange, instrument: &Instrument) -> String {
+        let settings = self.settings.exchanges.get(exchange.name());
+        settings
+            .and_then(|settings| {
+                settings.symbols.iter().find_map(|(canonical, symbol)| {
+                    match canonical.parse::<Instrument>() {
+                        Ok(canonical) if canonical == *instrument => Some(symbol.clone()),
+                        _ => None,
+                    }
+                })
+            })
+            .unwrap_or_else(|| {
+                settings
+                    .and_then(|settings| settings.symbol_format)
+                    .unwrap_or(exchange.capabilities().symbol_format)
+                    .format(instrument)
+            })
+    }
+
+    /// The instrument built from two legs in place of the symbol on the given exchange,
+    /// if the configuration file has one.
+    pub fn synthetic_instrument(&self, exchange: &dyn Exchange) -> Option<SyntheticInstrument> {
+        self.settings
+            .exchanges
+            .get(exchange.name())
+            .and_then(|settings| settings.synthetic_instrument(&self.symbol))
+            // Synthetic instruments are checked when the configuration file is loaded.
+            .and_then(Result::ok)
+    }
+
+    /// The base URL of the exchange's REST API.
+    pub fn rest_endpoint(&self, exchange: &dyn Exchange) -> String {
+        self.settings
+            .exchanges
+            .get(exchange.name())
+            .and_then(|settings| settings.rest_endpoint.clone())
+            .unwrap_or_else(|| exchange.rest_endpoint().to_string())
+    }
+
+    /// The trading fees and order size limits of the configured exchanges.
+    /// `<exchange-name> => <exchange-terms>`
+    pub fn exchange_terms(&self) -> HashMap<String, ExchangeTerms> {
+        self.settings
+            .exchanges
+            .iter()
+            .map(|(exchange, settings)| {
+                let terms = ExchangeTerms {
+                    taker_fee_bps: settings.taker_fee_bps,
+                    min_order_size: settings.min_order_size,
+                    synthetic: settings.synthetic_instrument(&self.symbol).is_some(),
+                };
+                (exchange.clone(), terms)
+            })
+            .collect()
+    }
+}
+
+impl Settings {
+    /// The given exchanges without the disabled ones, followed by the enabled ones not among them.
+    fn enabled_exchanges(&self, mut exchanges: Vec<String>) -> Vec<String> {
+        exchanges.retain(|exchange| {
+            self.exchanges
+                .get(exchange)
+                .and_then(|settings| settings.enabled)
+                != Some(false)
+        });
+        let mut enabled: Vec<_> = self
+            .exchanges
+            .iter()
+            .filter(|(exchange, settings)| {
+                settings.enabled == Some(true) && !exchanges.contains(exchange)
+            })
+            .map(|(exchange, _)| exchange.clone())
+            .collect();
+        enabled.sort();
+        exchanges.extend(enabled);
+        exchanges
+    }
+
+    fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read the configuration file '{}':  {}", path, err))?;
+        let settings: Self = toml::from_str(&contents).map_err(|err| {
+            format!(
+                "Failed to parse the configuration file '{}':  {}",
+                path, err
+            )
+        })?;
+        for (exchange, exchange_settings) in &settings.exchanges {
+            if !(0.0..10_000.0).contains(&exchange_settings.taker_fee_bps) {
+                return Err(format!(
+                    "The taker fee of {} must be between 0 and 10000 basis points.",
+                    exchange
+                ));
+            }
+            if !(exchange_settings.min_order_size >= 0.0
+                && exchange_settings.min_order_size.is_finite())
+            {
+                return Err(format!(
+                    "The minimum order size of {} must not be negative.",
+                    exchange
+                ));
+            }
+            for (canonical, legs) in &exchange_settings.synthetic {
+                canonical
+                    .parse::<Instrument>()
+                    .and_then(|instrument| parse_synthetic(instrument, legs))
+                    .map_err(|err| {
+                        format!("Invalid synthetic instrument of {}:  {}", exchange, err)
+                    })?;
+            }
         }
+        for rule in &settings.alerts.rules {
+            rule.validate()?;
+        }
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Settings;
+
+    #[test]
+    fn enable_and_disable_exchanges() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [exchanges.binance]
+            enabled = false
+            [exchanges.bitstamp]
+            taker_fee_bps = 10
+            [exchanges.kraken]
+            enabled = true
+            [exchanges.bitfinex]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+        let exchanges = vec!["binance".to_string(), "bitstamp".to_string()];
+        assert_eq!(
+            vec!["bitstamp", "bitfinex", "kraken"],
+            settings.enabled_exchanges(exchanges)
+        );
     }
 }
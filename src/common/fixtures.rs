@@ -0,0 +1,32 @@
+use crate::common::OrderBook;
+use crate::proto;
+
+pub fn level(exchange: &str, price: f64, amount: f64) -> proto::Level {
+    proto::Level {
+        exchange: exchange.to_string(),
+        price,
+        amount,
+        ..Default::default()
+    }
+}
+
+/// An order-book of the exchange with `(<price>, <amount>)` bids and asks, best price first.
+pub fn order_book(exchange: &'static str, bids: &[(f64, f64)], asks: &[(f64, f64)]) -> OrderBook {
+    let levels = |levels: &[(f64, f64)]| {
+        levels
+            .iter()
+            .map(|&(price, amount)| level(exchange, price, amount))
+            .collect()
+    };
+    OrderBook {
+        exchange,
+        bids: levels(bids),
+        asks: levels(asks),
+        received_ns: 0,
+    }
+}
+
+/// An order-book of the exchange with a bid and an ask of one each.
+pub fn top_of_book(exchange: &'static str, bid: f64, ask: f64) -> OrderBook {
+    order_book(exchange, &[(bid, 1.0)], &[(ask, 1.0)])
+}
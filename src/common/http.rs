@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// A minimal HTTP client, so that REST requests can be served
+/// by a local stand-in or a stub in tests.
+#[async_trait]
+pub trait HttpClient: Send + Sync {
+    async fn get(&self, url: &str) -> Result<HttpResponse, Box<dyn Error + Send + Sync>>;
+
+    /// POST a JSON body.
+    async fn post_json(
+        &self,
+        url: &str,
+        body: String,
+    ) -> Result<HttpResponse, Box<dyn Error + Send + Sync>>;
+}
+
+/// HTTP client backed by `reqwest`.
+#[derive(Default)]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+}
+
+impl ReqwestClient {
+    /// A client whose requests fail after `timeout`.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Failed to build the HTTP client."),
+        }
+    }
+}
+
+#[async_trait]
+impl HttpClient for ReqwestClient {
+    async fn get(&self, url: &str) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
+        let response = self.client.get(url).send().await?;
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+        Ok(HttpResponse { status, body })
+    }
+
+    async fn post_json(
+        &self,
+        url: &str,
+        body: String,
+    ) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
+        let response = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+        Ok(HttpResponse { status, body })
+    }
+}
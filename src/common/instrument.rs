@@ -0,0 +1,113 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Quote currencies recognised when splitting a concatenated symbol such as `ethbtc`.
+/// Longer codes come first so that eg. `USDT` is matched before `USD`.
+const KNOWN_QUOTES: &[&str] = &[
+    "USDT", "USDC", "BUSD", "TUSD", "USD", "EUR", "GBP", "JPY", "BTC", "XBT", "ETH", "BNB", "DAI",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MarketType {
+    Spot,
+    Perpetual,
+}
+
+/// Exchange-independent description of a tradable instrument.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Instrument {
+    /// Upper-case base currency code, eg. `ETH`.
+    pub base: String,
+    /// Upper-case quote currency code, eg. `BTC`.
+    pub quote: String,
+    pub market: MarketType,
+}
+
+impl Instrument {
+    pub fn spot(base: &str, quote: &str) -> Self {
+        Self {
+            base: normalize_currency(base),
+            quote: normalize_currency(quote),
+            market: MarketType::Spot,
+        }
+    }
+}
+
+/// Upper-case a currency code and replace exchange-specific aliases
+/// with the canonical code.
+fn normalize_currency(code: &str) -> String {
+    match code.to_uppercase().as_str() {
+        "XBT" => "BTC".to_string(),
+        other => other.to_string(),
+    }
+}
+
+impl FromStr for Instrument {
+    type Err = String;
+
+    /// Parse a symbol of the form `ETH/BTC`, `ETH-BTC`, `ETH_BTC` or `ethbtc`,
+    /// optionally followed by `:PERP` for perpetual contracts.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pair, market) = match s.split_once(':') {
+            None => (s, MarketType::Spot),
+            Some((pair, market)) if market.eq_ignore_ascii_case("perp") => {
+                (pair, MarketType::Perpetual)
+            }
+            Some((_, market)) => return Err(format!("Unknown market type '{}'.", market)),
+        };
+
+        let (base, quote) = match pair.split_once(['/', '-', '_']) {
+            Some(split) => split,
+            None => {
+                let upper = pair.to_uppercase();
+                let quote = KNOWN_QUOTES
+                    .iter()
+                    .find(|quote| upper.len() > quote.len() && upper.ends_with(*quote))
+                    .ok_or_else(|| format!("Unable to determine the quote currency of '{}'.", s))?;
+                pair.split_at(pair.len() - quote.len())
+            }
+        };
+        if base.is_empty() || quote.is_empty() {
+            return Err(format!("Invalid symbol '{}'.", s));
+        }
+
+        Ok(Self {
+            market,
+            ..Self::spot(base, quote)
+        })
+    }
+}
+
+impl fmt::Display for Instrument {
+    /// The canonical form, eg. `ETH/BTC`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)?;
+        if self.market == MarketType::Perpetual {
+            write!(f, ":PERP")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Instrument, MarketType};
+
+    #[test]
+    fn parse_instrument() {
+        let eth_btc = Instrument::spot("ETH", "BTC");
+        assert_eq!(Ok(eth_btc.clone()), "ethbtc".parse());
+        assert_eq!(Ok(eth_btc.clone()), "ETH/BTC".parse());
+        assert_eq!(Ok(eth_btc.clone()), "eth-xbt".parse());
+        assert_eq!(Ok(eth_btc), "ETH_BTC".parse());
+        assert_eq!(
+            Ok(Instrument::spot("ETH", "USDT")),
+            "ethusdt".parse::<Instrument>()
+        );
+        assert_eq!(
+            MarketType::Perpetual,
+            "BTC/USDT:PERP".parse::<Instrument>().unwrap().market
+        );
+        assert!("foobar".parse::<Instrument>().is_err());
+    }
+}
@@ -1,15 +1,67 @@
 pub mod config;
+/// Order-books and levels for the tests.
+#[cfg(test)]
+pub mod fixtures;
+pub mod http;
+pub mod instrument;
 
 use crate::proto;
 use serde::{Deserialize, Deserializer};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Generic order-book.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct OrderBook {
     pub exchange: &'static str,
     pub bids: Vec<proto::Level>,
     pub asks: Vec<proto::Level>,
+    /// Receive time of the frame in nanoseconds since the Unix epoch.
+    pub received_ns: i64,
+}
+
+impl OrderBook {
+    pub fn empty(exchange: &'static str) -> Self {
+        Self {
+            exchange,
+            bids: vec![],
+            asks: vec![],
+            received_ns: 0,
+        }
+    }
+
+    /// Apply an incremental update to this order-book, and take its receive time.
+    /// A level with a zero amount removes that price level.
+    pub fn apply_diff(&mut self, diff: OrderBook) {
+        apply_level_diff(&mut self.bids, diff.bids, |a, b| {
+            b.price.partial_cmp(&a.price).unwrap()
+        });
+        apply_level_diff(&mut self.asks, diff.asks, |a, b| {
+            a.price.partial_cmp(&b.price).unwrap()
+        });
+        self.received_ns = diff.received_ns;
+    }
+}
+
+fn apply_level_diff<F>(levels: &mut Vec<proto::Level>, diff: Vec<proto::Level>, order: F)
+where
+    F: Fn(&proto::Level, &proto::Level) -> std::cmp::Ordering,
+{
+    for update in diff {
+        levels.retain(|level| level.price != update.price);
+        if update.amount > 0.0 {
+            levels.push(update);
+        }
+    }
+    levels.sort_by(order);
+}
+
+/// The current time in nanoseconds since the Unix epoch.
+pub fn now_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -18,6 +70,26 @@ pub struct OrderBookEntry {
     pub quantity: f64,
 }
 
+impl proto::Summary {
+    /// The summary limited to the top `depth` levels of each side.
+    pub fn with_depth(&self, depth: usize) -> Self {
+        Self {
+            spread: self.spread,
+            bids: self.bids.iter().take(depth).cloned().collect(),
+            asks: self.asks.iter().take(depth).cloned().collect(),
+        }
+    }
+
+    /// The best bid and ask of the summary.
+    pub fn best_bid_offer(&self) -> proto::TopOfBook {
+        proto::TopOfBook {
+            bid: self.bids.first().cloned(),
+            ask: self.asks.first().cloned(),
+            spread: self.spread,
+        }
+    }
+}
+
 /// Deserialize order-book entries of the following format:
 /// ```json
 /// [ ["<price>", "<quantity>"], ["<price>", "<quantity>"] ]
@@ -40,6 +112,15 @@ where
     Ok(entries)
 }
 
+/// Deserialize a number which is encoded as a string, eg. `"0.00000100"`.
+pub fn deserialize_str_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    f64::from_str(&s).map_err(serde::de::Error::custom)
+}
+
 pub fn order_book_entries_to_rpc_levels(
     exchange_name: &str,
     entries: Vec<OrderBookEntry>,
@@ -50,6 +131,48 @@ pub fn order_book_entries_to_rpc_levels(
             exchange: exchange_name.to_string(),
             price: entry.price,
             amount: entry.quantity,
+            ..Default::default()
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::common::fixtures::{level, order_book};
+    use crate::proto;
+
+    #[test]
+    fn apply_order_book_diff() {
+        let mut book = order_book(
+            "test",
+            &[(10.0, 1.0), (9.0, 1.0)],
+            &[(11.0, 1.0), (12.0, 1.0)],
+        );
+        let mut diff = order_book("test", &[(9.0, 0.0), (9.5, 2.0)], &[(11.0, 3.0)]);
+        diff.received_ns = 2;
+        book.apply_diff(diff);
+
+        let expected = order_book(
+            "test",
+            &[(10.0, 1.0), (9.5, 2.0)],
+            &[(11.0, 3.0), (12.0, 1.0)],
+        );
+        assert_eq!(expected.bids, book.bids);
+        assert_eq!(expected.asks, book.asks);
+        assert_eq!(2, book.received_ns);
+    }
+
+    #[test]
+    fn summary_best_bid_offer() {
+        let summary = proto::Summary {
+            spread: 0.5,
+            bids: vec![level("a", 10.0, 1.0), level("b", 9.5, 2.0)],
+            asks: vec![level("b", 10.5, 1.0), level("a", 11.0, 2.0)],
+        };
+        let top = summary.best_bid_offer();
+        assert_eq!(Some(level("a", 10.0, 1.0)), top.bid);
+        assert_eq!(Some(level("b", 10.5, 1.0)), top.ask);
+        assert_eq!(0.5, top.spread);
+        assert!(proto::Summary::default().best_bid_offer().bid.is_none());
+    }
+}
@@ -1,17 +1,20 @@
-use std::error::Error;
-
+use crate::common::http::HttpResponse;
 use crate::common::{order_book_entries_to_rpc_levels, OrderBookEntry};
-use crate::exchange::Exchange;
+use crate::exchange::metadata::{unexpected_response, InstrumentInfo, InstrumentStatus};
+use crate::exchange::{BookUpdate, Capabilities, Exchange, ExchangeError, SymbolFormat, WsStream};
 use crate::OrderBook;
 use async_trait::async_trait;
 use serde::Deserialize;
-use tokio::net::TcpStream;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::connect_async;
 use url::Url;
 
 static EXCHANGE_NAME: &str = "binance";
 const STREAM_ENDPOINT: &str = "wss://stream.binance.com:9443/ws/";
 const STREAM_SUFFIX: &str = "@depth10@100ms";
+const STREAM_DEPTH: usize = 10;
+const REST_ENDPOINT: &str = "https://api.binance.com";
+/// The error code returned by `exchangeInfo` for an unknown symbol.
+const INVALID_SYMBOL_CODE: i64 = -1121;
 
 pub struct Binance;
 
@@ -25,39 +28,145 @@ pub struct BinanceOrderBookMessage {
     pub asks: Vec<OrderBookEntry>,
 }
 
-impl Into<OrderBook> for BinanceOrderBookMessage {
-    fn into(self) -> OrderBook {
-        let mut bids = order_book_entries_to_rpc_levels(EXCHANGE_NAME, self.bids);
+#[derive(Debug, Deserialize)]
+pub struct BinanceExchangeInfo {
+    pub symbols: Vec<BinanceSymbolInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BinanceSymbolInfo {
+    pub symbol: String,
+    pub status: String,
+    pub filters: Vec<BinanceSymbolFilter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "filterType")]
+pub enum BinanceSymbolFilter {
+    #[serde(rename = "PRICE_FILTER")]
+    Price {
+        #[serde(rename = "tickSize")]
+        #[serde(deserialize_with = "crate::common::deserialize_str_f64")]
+        tick_size: f64,
+    },
+    #[serde(rename = "LOT_SIZE")]
+    LotSize {
+        #[serde(rename = "stepSize")]
+        #[serde(deserialize_with = "crate::common::deserialize_str_f64")]
+        step_size: f64,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct BinanceErrorResponse {
+    code: i64,
+}
+
+impl From<BinanceOrderBookMessage> for OrderBook {
+    fn from(msg: BinanceOrderBookMessage) -> Self {
+        let mut bids = order_book_entries_to_rpc_levels(EXCHANGE_NAME, msg.bids);
         bids.sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
-        let mut asks = order_book_entries_to_rpc_levels(EXCHANGE_NAME, self.asks);
+        let mut asks = order_book_entries_to_rpc_levels(EXCHANGE_NAME, msg.asks);
         asks.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
         OrderBook {
             exchange: EXCHANGE_NAME,
             bids,
             asks,
+            // Set by the book builder.
+            received_ns: 0,
         }
     }
 }
 
 #[async_trait]
 impl Exchange for Binance {
-    type OrderBookMessage = BinanceOrderBookMessage;
+    fn name(&self) -> &'static str {
+        EXCHANGE_NAME
+    }
 
-    async fn connect(
-        trading_pair: &str,
-    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn Error>> {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            book_update: BookUpdate::Snapshot,
+            max_depth: STREAM_DEPTH,
+            symbol_format: SymbolFormat::LowerConcat,
+        }
+    }
+
+    async fn connect(&self, trading_pair: &str) -> Result<WsStream, ExchangeError> {
         let stream_endpoint = format!("{}{}{}", STREAM_ENDPOINT, trading_pair, STREAM_SUFFIX);
         let url = Url::parse(&stream_endpoint)?;
 
         let (ws, _) = connect_async(url).await?;
         Ok(ws)
     }
+
+    fn parse_order_book(&self, text: &str) -> Result<OrderBook, serde_json::Error> {
+        serde_json::from_str::<BinanceOrderBookMessage>(text).map(OrderBook::from)
+    }
+
+    fn rest_endpoint(&self) -> &'static str {
+        REST_ENDPOINT
+    }
+
+    fn instrument_info_url(&self, rest_endpoint: &str, trading_pair: &str) -> String {
+        // The REST API only accepts upper-case symbols.
+        format!(
+            "{}/api/v3/exchangeInfo?symbol={}",
+            rest_endpoint.trim_end_matches('/'),
+            trading_pair.to_uppercase()
+        )
+    }
+
+    fn parse_instrument_info(
+        &self,
+        response: &HttpResponse,
+        trading_pair: &str,
+    ) -> Result<Option<InstrumentInfo>, ExchangeError> {
+        if !response.is_success() {
+            return match serde_json::from_str::<BinanceErrorResponse>(&response.body) {
+                Ok(err) if err.code == INVALID_SYMBOL_CODE => Ok(None),
+                _ => Err(unexpected_response(response)),
+            };
+        }
+        let exchange_info = serde_json::from_str::<BinanceExchangeInfo>(&response.body)?;
+        let symbol_info = match exchange_info
+            .symbols
+            .into_iter()
+            .find(|s| s.symbol.eq_ignore_ascii_case(trading_pair))
+        {
+            None => return Ok(None),
+            Some(symbol_info) => symbol_info,
+        };
+
+        let mut info = InstrumentInfo {
+            symbol: trading_pair.to_string(),
+            status: match symbol_info.status.as_str() {
+                "TRADING" => InstrumentStatus::Trading,
+                _ => InstrumentStatus::Halted,
+            },
+            tick_size: 0.0,
+            lot_size: 0.0,
+        };
+        for filter in symbol_info.filters {
+            match filter {
+                BinanceSymbolFilter::Price { tick_size } => info.tick_size = tick_size,
+                BinanceSymbolFilter::LotSize { step_size } => info.lot_size = step_size,
+                BinanceSymbolFilter::Other => {}
+            }
+        }
+        Ok(Some(info))
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::common::http::HttpResponse;
     use crate::common::OrderBookEntry;
-    use crate::exchange::binance::BinanceOrderBookMessage;
+    use crate::exchange::binance::{Binance, BinanceOrderBookMessage};
+    use crate::exchange::metadata::{InstrumentInfo, InstrumentStatus};
+    use crate::exchange::Exchange;
 
     #[test]
     fn deserialize_order_book_message() {
@@ -91,4 +200,27 @@ mod tests {
         assert!(actual.is_ok());
         assert_eq!(expected, actual.unwrap());
     }
+
+    #[test]
+    fn parse_instrument_info() {
+        let response = HttpResponse {
+            status: 200,
+            body: include_str!("../../tests/binance_exchange_info.json").to_string(),
+        };
+        let expected = InstrumentInfo {
+            symbol: "ethbtc".to_string(),
+            status: InstrumentStatus::Trading,
+            tick_size: 0.000001,
+            lot_size: 0.0001,
+        };
+        let actual = Binance.parse_instrument_info(&response, "ethbtc");
+        assert_eq!(Some(expected), actual.unwrap());
+
+        let unknown_symbol = HttpResponse {
+            status: 400,
+            body: r#"{"code":-1121,"msg":"Invalid symbol."}"#.to_string(),
+        };
+        let actual = Binance.parse_instrument_info(&unknown_symbol, "ethfoo");
+        assert_eq!(None, actual.unwrap());
+    }
 }
@@ -1,21 +1,26 @@
+use crate::common::http::HttpResponse;
 use crate::common::{order_book_entries_to_rpc_levels, OrderBookEntry};
-use crate::exchange::Exchange;
+use crate::exchange::metadata::{
+    decimals_to_increment, unexpected_response, InstrumentInfo, InstrumentStatus,
+};
+use crate::exchange::{BookUpdate, Capabilities, Exchange, ExchangeError, SymbolFormat, WsStream};
 use crate::OrderBook;
 use async_trait::async_trait;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use core::str::FromStr;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Deserializer};
 use serde_json::json;
-use std::error::Error;
 use std::io::ErrorKind;
-use tokio::net::TcpStream;
+use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use url::Url;
 
 static EXCHANGE_NAME: &str = "bitstamp";
 const STREAM_ENDPOINT: &str = "wss://ws.bitstamp.net/";
+/// The `order_book` channel publishes the top 100 levels of each side.
+const STREAM_DEPTH: usize = 100;
+const REST_ENDPOINT: &str = "https://www.bitstamp.net";
 
 pub struct Bitstamp;
 
@@ -36,37 +41,56 @@ pub struct BitstampOrderBookMessageData {
     pub asks: Vec<OrderBookEntry>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BitstampTradingPairInfo {
+    pub url_symbol: String,
+    pub base_decimals: i32,
+    pub counter_decimals: i32,
+    /// `Enabled` or `Disabled`.
+    pub trading: String,
+}
+
 fn deserialize_order_book_ts<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
     let unix_timestamp = i64::from_str(&s).map_err(serde::de::Error::custom)?;
-    let ndt = NaiveDateTime::from_timestamp(unix_timestamp, 0);
-    Ok(DateTime::from_utc(ndt, Utc))
+    DateTime::from_timestamp(unix_timestamp, 0)
+        .ok_or_else(|| serde::de::Error::custom("Timestamp out of range."))
 }
 
-impl Into<OrderBook> for BitstampOrderBookMessage {
-    fn into(self) -> OrderBook {
-        let mut bids = order_book_entries_to_rpc_levels(EXCHANGE_NAME, self.data.bids);
+impl From<BitstampOrderBookMessage> for OrderBook {
+    fn from(msg: BitstampOrderBookMessage) -> Self {
+        let mut bids = order_book_entries_to_rpc_levels(EXCHANGE_NAME, msg.data.bids);
         bids.sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
-        let mut asks = order_book_entries_to_rpc_levels(EXCHANGE_NAME, self.data.asks);
+        let mut asks = order_book_entries_to_rpc_levels(EXCHANGE_NAME, msg.data.asks);
         asks.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
         OrderBook {
             exchange: EXCHANGE_NAME,
             bids,
             asks,
+            // Set by the book builder.
+            received_ns: 0,
         }
     }
 }
 
 #[async_trait]
 impl Exchange for Bitstamp {
-    type OrderBookMessage = BitstampOrderBookMessage;
+    fn name(&self) -> &'static str {
+        EXCHANGE_NAME
+    }
 
-    async fn connect(
-        trading_pair: &str,
-    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn Error>> {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            book_update: BookUpdate::Snapshot,
+            max_depth: STREAM_DEPTH,
+            symbol_format: SymbolFormat::LowerConcat,
+        }
+    }
+
+    async fn connect(&self, trading_pair: &str) -> Result<WsStream, ExchangeError> {
         let url = Url::parse(STREAM_ENDPOINT)?;
         let (mut ws, _) = connect_async(url).await?;
 
@@ -100,6 +124,44 @@ impl Exchange for Bitstamp {
 
         Ok(ws)
     }
+
+    fn parse_order_book(&self, text: &str) -> Result<OrderBook, serde_json::Error> {
+        serde_json::from_str::<BitstampOrderBookMessage>(text).map(OrderBook::from)
+    }
+
+    fn rest_endpoint(&self) -> &'static str {
+        REST_ENDPOINT
+    }
+
+    fn instrument_info_url(&self, rest_endpoint: &str, _trading_pair: &str) -> String {
+        format!(
+            "{}/api/v2/trading-pairs-info/",
+            rest_endpoint.trim_end_matches('/')
+        )
+    }
+
+    fn parse_instrument_info(
+        &self,
+        response: &HttpResponse,
+        trading_pair: &str,
+    ) -> Result<Option<InstrumentInfo>, ExchangeError> {
+        if !response.is_success() {
+            return Err(unexpected_response(response));
+        }
+        let pairs = serde_json::from_str::<Vec<BitstampTradingPairInfo>>(&response.body)?;
+        Ok(pairs
+            .into_iter()
+            .find(|pair| pair.url_symbol == trading_pair)
+            .map(|pair| InstrumentInfo {
+                symbol: pair.url_symbol,
+                status: match pair.trading.as_str() {
+                    "Enabled" => InstrumentStatus::Trading,
+                    _ => InstrumentStatus::Halted,
+                },
+                tick_size: decimals_to_increment(pair.counter_decimals),
+                lot_size: decimals_to_increment(pair.base_decimals),
+            }))
+    }
 }
 
 fn subscription_request(trading_pair: &str) -> String {
@@ -114,8 +176,11 @@ fn subscription_request(trading_pair: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::{BitstampOrderBookMessage, BitstampOrderBookMessageData};
+    use super::{Bitstamp, BitstampOrderBookMessage, BitstampOrderBookMessageData};
+    use crate::common::http::HttpResponse;
     use crate::common::OrderBookEntry;
+    use crate::exchange::metadata::{InstrumentInfo, InstrumentStatus};
+    use crate::exchange::Exchange;
     use chrono::TimeZone;
 
     #[test]
@@ -124,7 +189,9 @@ mod tests {
             event: "data".to_string(),
             channel: "order_book_ethbtc".to_string(),
             data: BitstampOrderBookMessageData {
-                timestamp: chrono::Utc.ymd(2022, 1, 8).and_hms(13, 14, 33),
+                timestamp: chrono::Utc
+                    .with_ymd_and_hms(2022, 1, 8, 13, 14, 33)
+                    .unwrap(),
                 bids: vec![
                     OrderBookEntry {
                         price: 0.07638925,
@@ -154,4 +221,25 @@ mod tests {
         assert!(actual.is_ok());
         assert_eq!(expected, actual.unwrap());
     }
+
+    #[test]
+    fn parse_instrument_info() {
+        let response = HttpResponse {
+            status: 200,
+            body: include_str!("../../tests/bitstamp_trading_pairs_info.json").to_string(),
+        };
+        let expected = InstrumentInfo {
+            symbol: "ethbtc".to_string(),
+            status: InstrumentStatus::Trading,
+            tick_size: 0.00000001,
+            lot_size: 0.00000001,
+        };
+        let actual = Bitstamp.parse_instrument_info(&response, "ethbtc").unwrap();
+        assert_eq!(Some(expected), actual);
+
+        let disabled = Bitstamp.parse_instrument_info(&response, "xrpbtc").unwrap();
+        assert_eq!(InstrumentStatus::Halted, disabled.unwrap().status);
+        let unlisted = Bitstamp.parse_instrument_info(&response, "ethfoo").unwrap();
+        assert_eq!(None, unlisted);
+    }
 }
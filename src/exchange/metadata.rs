@@ -0,0 +1,112 @@
+use std::fmt;
+
+use crate::common::http::HttpResponse;
+use crate::exchange::ExchangeError;
+
+/// Whether an instrument can currently be traded on an exchange.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstrumentStatus {
+    Trading,
+    Halted,
+}
+
+/// An exchange's metadata for one instrument.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstrumentInfo {
+    /// The exchange-specific symbol used for stream subscriptions.
+    pub symbol: String,
+    pub status: InstrumentStatus,
+    /// The minimum price increment.
+    pub tick_size: f64,
+    /// The minimum quantity increment.
+    pub lot_size: f64,
+}
+
+impl InstrumentInfo {
+    /// Metadata for a symbol which has not been checked with the exchange.
+    /// Prices and quantities are left as published.
+    pub fn unchecked(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            status: InstrumentStatus::Trading,
+            tick_size: 0.0,
+            lot_size: 0.0,
+        }
+    }
+
+    pub fn round_price(&self, price: f64) -> f64 {
+        round_to_increment(price, self.tick_size)
+    }
+
+    pub fn round_quantity(&self, quantity: f64) -> f64 {
+        round_to_increment(quantity, self.lot_size)
+    }
+}
+
+impl fmt::Display for InstrumentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?}, tick size {}, lot size {}",
+            self.symbol, self.status, self.tick_size, self.lot_size
+        )
+    }
+}
+
+/// The most decimal places of an increment.
+const MAX_DECIMALS: i32 = 12;
+
+/// Round to the nearest multiple of the increment, and then to the increment's decimal places,
+/// so that eg. a price of `0.076424` with a tick size of `0.000001` stays exactly `0.076424`
+/// rather than picking up the noise of `76424.0 * 0.000001`.
+fn round_to_increment(value: f64, increment: f64) -> f64 {
+    if increment > 0.0 {
+        let scale = 10f64.powi(decimal_places(increment));
+        ((value / increment).round() * increment * scale).round() / scale
+    } else {
+        value
+    }
+}
+
+/// The decimal places of an increment, eg. `0.25 => 2`.
+fn decimal_places(increment: f64) -> i32 {
+    (0..MAX_DECIMALS)
+        .find(|&decimals| {
+            let scaled = increment * 10f64.powi(decimals);
+            (scaled - scaled.round()).abs() < 1e-9 * scaled
+        })
+        .unwrap_or(MAX_DECIMALS)
+}
+
+/// Decimal places to a power-of-ten increment, eg. `8 => 0.00000001`.
+pub fn decimals_to_increment(decimals: i32) -> f64 {
+    10f64.powi(-decimals)
+}
+
+/// The error for an instrument metadata request which the exchange did not answer successfully.
+pub fn unexpected_response(response: &HttpResponse) -> ExchangeError {
+    format!(
+        "Unexpected instrument metadata response ({}):  {}",
+        response.status, response.body
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decimal_places, round_to_increment};
+
+    #[test]
+    fn round_to_increments() {
+        assert_eq!(6, decimal_places(0.000001));
+        assert_eq!(2, decimal_places(0.25));
+        assert_eq!(0, decimal_places(5.0));
+
+        assert_eq!(0.076424, round_to_increment(0.076424, 0.000001));
+        assert_eq!(0.3, round_to_increment(0.1 + 0.2, 0.1));
+        assert_eq!(10.75, round_to_increment(10.8, 0.25));
+        assert_eq!(1.2345678, round_to_increment(1.2345678, 0.0));
+        // Less than half a lot rounds to nothing.
+        assert_eq!(0.0, round_to_increment(0.0004, 0.001));
+    }
+}
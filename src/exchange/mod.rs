@@ -1,4 +1,5 @@
 use std::error::Error;
+use std::fmt;
 
 use async_trait::async_trait;
 use futures_util::{SinkExt, StreamExt};
@@ -8,27 +9,126 @@ use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
+use crate::common::http::{HttpClient, HttpResponse};
 use crate::common::OrderBook;
+use crate::exchange::metadata::InstrumentInfo;
+use crate::recorder::{FrameRecorder, RawFrame};
+use crate::state::{ConnectionStatus, MarketState};
 
 pub mod binance;
 pub mod bitstamp;
+pub mod metadata;
+pub mod registry;
+pub mod symbol;
+
+pub use registry::ExchangeRegistry;
+pub use symbol::SymbolFormat;
+
+pub type ExchangeError = Box<dyn Error + Send + Sync>;
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How an exchange publishes its order-book stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookUpdate {
+    /// Every message is a complete, depth-limited order-book.
+    Snapshot,
+    /// Messages are incremental changes to a previously received order-book.
+    /// None of the built-in adapters use diff streams yet.
+    #[allow(dead_code)]
+    Diff,
+}
+
+/// What an exchange adapter supports.
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+    pub book_update: BookUpdate,
+    /// The maximum number of price levels per side in each message.
+    pub max_depth: usize,
+    pub symbol_format: SymbolFormat,
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} updates, depth {}, {:?} symbols",
+            self.book_update, self.max_depth, self.symbol_format
+        )
+    }
+}
 
 #[async_trait]
-pub trait Exchange {
-    type OrderBookMessage: for<'a> serde::Deserialize<'a> + Into<OrderBook> + Send;
+pub trait Exchange: Send + Sync {
+    /// The unique name of the exchange, used as the registry key and
+    /// to attribute order-book levels.
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
 
     /// Exchange-specific logic to connect to the exchange's websocket
     /// and subscribe to the appropriate order book stream.
-    async fn connect(
+    async fn connect(&self, trading_pair: &str) -> Result<WsStream, ExchangeError>;
+
+    /// Deserialize a websocket text message into an order-book.
+    fn parse_order_book(&self, text: &str) -> Result<OrderBook, serde_json::Error>;
+
+    /// The default base URL of the exchange's REST API.
+    fn rest_endpoint(&self) -> &'static str;
+
+    /// The URL of the exchange's instrument metadata for the symbol.
+    fn instrument_info_url(&self, rest_endpoint: &str, trading_pair: &str) -> String;
+
+    /// Find the symbol in the exchange's instrument metadata response.
+    /// Returns `None` if the exchange does not list the symbol.
+    fn parse_instrument_info(
+        &self,
+        response: &HttpResponse,
+        trading_pair: &str,
+    ) -> Result<Option<InstrumentInfo>, ExchangeError>;
+
+    /// Fetch the exchange's metadata for the symbol.
+    async fn instrument_info(
+        &self,
+        http: &dyn HttpClient,
+        rest_endpoint: &str,
         trading_pair: &str,
-    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Box<dyn Error>>;
+    ) -> Result<Option<InstrumentInfo>, ExchangeError> {
+        let url = self.instrument_info_url(rest_endpoint, trading_pair);
+        debug!("Fetching instrument metadata from {}", url);
+        let response = http.get(&url).await?;
+        self.parse_instrument_info(&response, trading_pair)
+    }
 
     /// Connect and read from the exchange's websocket stream.
     async fn start(
-        trading_symbol: &str,
+        &self,
+        instrument: &InstrumentInfo,
+        recorder: Option<&FrameRecorder>,
+        state: &MarketState,
+        sink: mpsc::Sender<OrderBook>,
+    ) -> Result<(), ExchangeError> {
+        let stream = self.connect(&instrument.symbol).await?;
+        state.set_status(
+            self.name(),
+            ConnectionStatus::Subscribed,
+            format!("Subscribed to '{}'.", instrument.symbol),
+            None,
+        );
+        self.read(stream, instrument, recorder, sink).await
+    }
+
+    /// Read from a subscribed websocket stream until it closes.
+    /// Prices and quantities are rounded to the instrument's tick and lot sizes.
+    /// Every text frame is passed to the recorder, if there is one.
+    async fn read(
+        &self,
+        stream: WsStream,
+        instrument: &InstrumentInfo,
+        recorder: Option<&FrameRecorder>,
         sink: mpsc::Sender<OrderBook>,
-    ) -> Result<(), Box<dyn Error>> {
-        let (mut tx, mut rx) = Self::connect(trading_symbol).await?.split();
+    ) -> Result<(), ExchangeError> {
+        let (mut tx, mut rx) = stream.split();
+        let mut book_builder = BookBuilder::new(self, instrument);
 
         // Read from the stream.
         while let Some(message) = rx.next().await {
@@ -40,10 +140,15 @@ pub trait Exchange {
                 Ok(m) => match m {
                     Message::Text(text) => {
                         debug!("Text message received:  {}", text);
-                        match serde_json::from_str::<Self::OrderBookMessage>(&text) {
+                        let frame = RawFrame::received_now(self.name(), &instrument.symbol, &text);
+                        let order_book = book_builder.build(&frame);
+                        if let Some(recorder) = recorder {
+                            recorder.record(frame);
+                        }
+                        match order_book {
                             Err(err) => error!("Error deserializing message:  {}", err),
-                            Ok(order_book_msg) => {
-                                if let Err(err) = sink.send(order_book_msg.into()).await {
+                            Ok(order_book) => {
+                                if let Err(err) = sink.send(order_book).await {
                                     error!(
                                         "Error sending order book message on the channel:  {}",
                                         err
@@ -54,7 +159,7 @@ pub trait Exchange {
                     }
                     Message::Ping(_) => {
                         debug!("Received PING.  Sending PONG.");
-                        tx.send(Message::Pong(vec![0; 0])).await.unwrap();
+                        tx.send(Message::Pong(vec![0; 0])).await?;
                     }
                     Message::Pong(_) => debug!("Received PONG."),
                     Message::Binary(_) => debug!("Skipping binary message handling."),
@@ -65,3 +170,46 @@ pub trait Exchange {
         Ok(())
     }
 }
+
+/// Turns an exchange's text frames into normalised order-books.
+pub struct BookBuilder<'a, E: Exchange + ?Sized> {
+    exchange: &'a E,
+    instrument: &'a InstrumentInfo,
+    /// The local order-book for exchanges which only publish diffs.
+    local_book: OrderBook,
+}
+
+impl<'a, E: Exchange + ?Sized> BookBuilder<'a, E> {
+    pub fn new(exchange: &'a E, instrument: &'a InstrumentInfo) -> Self {
+        Self {
+            exchange,
+            instrument,
+            local_book: OrderBook::empty(exchange.name()),
+        }
+    }
+
+    /// Deserialize the frame and round its prices and quantities to the
+    /// instrument's tick and lot sizes, dropping the levels which round to nothing.
+    /// The diffs of exchanges which only publish diffs are applied to the local order-book.
+    pub fn build(&mut self, frame: &RawFrame) -> Result<OrderBook, serde_json::Error> {
+        let mut order_book = self.exchange.parse_order_book(&frame.text)?;
+        order_book.received_ns = frame.received_ns;
+        for level in order_book.bids.iter_mut().chain(order_book.asks.iter_mut()) {
+            level.price = self.instrument.round_price(level.price);
+            level.amount = self.instrument.round_quantity(level.amount);
+        }
+        Ok(match self.exchange.capabilities().book_update {
+            BookUpdate::Snapshot => {
+                // Levels of less than half a lot round to nothing.
+                order_book.bids.retain(|level| level.amount > 0.0);
+                order_book.asks.retain(|level| level.amount > 0.0);
+                order_book
+            }
+            // A level which rounds to nothing removes its price level.
+            BookUpdate::Diff => {
+                self.local_book.apply_diff(order_book);
+                self.local_book.clone()
+            }
+        })
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::exchange::binance::Binance;
+use crate::exchange::bitstamp::Bitstamp;
+use crate::exchange::Exchange;
+
+/// The exchange adapters available at runtime, keyed by exchange name.
+pub struct ExchangeRegistry {
+    exchanges: BTreeMap<&'static str, Arc<dyn Exchange>>,
+}
+
+impl ExchangeRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self {
+            exchanges: BTreeMap::new(),
+        }
+    }
+
+    /// Add an exchange adapter, replacing any adapter with the same name.
+    pub fn register(&mut self, exchange: Arc<dyn Exchange>) {
+        self.exchanges.insert(exchange.name(), exchange);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Exchange>> {
+        self.exchanges.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.exchanges.keys().copied()
+    }
+
+    /// Look up each of the named exchanges.
+    /// Fails on the first name that is not registered.
+    pub fn resolve(&self, names: &[String]) -> Result<Vec<Arc<dyn Exchange>>, String> {
+        names
+            .iter()
+            .map(|name| {
+                self.get(name).ok_or_else(|| {
+                    format!(
+                        "Unknown exchange '{}'.  Available exchanges:  {}",
+                        name,
+                        self.names().collect::<Vec<_>>().join(", ")
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl Default for ExchangeRegistry {
+    /// A registry containing all of the built-in exchange adapters.
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(Arc::new(Binance));
+        registry.register(Arc::new(Bitstamp));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExchangeRegistry;
+
+    #[test]
+    fn resolve_exchanges() {
+        let registry = ExchangeRegistry::default();
+
+        let exchanges = registry
+            .resolve(&["bitstamp".to_string(), "binance".to_string()])
+            .unwrap();
+        let names: Vec<&str> = exchanges.iter().map(|e| e.name()).collect();
+        assert_eq!(vec!["bitstamp", "binance"], names);
+
+        assert!(registry.resolve(&["kraken".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Deserialize;
+
+use crate::common::instrument::Instrument;
+
+/// The trading symbol format an exchange expects in its stream subscriptions.
+/// An adapter's format may be overridden in the configuration file.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolFormat {
+    /// Lower-case base and quote currencies concatenated, eg. `ethbtc`.
+    LowerConcat,
+    /// Upper-case base and quote currencies concatenated, eg. `ETHBTC`.
+    UpperConcat,
+    /// Upper-case currencies separated by a dash, eg. `ETH-BTC`.
+    Dash,
+    /// Upper-case currencies separated by an underscore, eg. `ETH_BTC`.
+    Underscore,
+    /// Kraken's legacy asset codes, eg. `XETHXBT` or `XXBTZUSD`.
+    Kraken,
+    /// Bitfinex trading pair symbols, eg. `tETHBTC` or `tTESTBTC:TESTUSD`.
+    Bitfinex,
+}
+
+/// Fiat currencies which Kraken prefixes with `Z` rather than `X`.
+const KRAKEN_FIAT: &[&str] = &["USD", "EUR", "GBP", "JPY", "CAD", "CHF", "AUD"];
+
+impl SymbolFormat {
+    /// The exchange-specific symbol of the instrument.
+    pub fn format(&self, instrument: &Instrument) -> String {
+        let (base, quote) = (instrument.base.as_str(), instrument.quote.as_str());
+        match self {
+            SymbolFormat::LowerConcat => format!("{}{}", base, quote).to_lowercase(),
+            SymbolFormat::UpperConcat => format!("{}{}", base, quote),
+            SymbolFormat::Dash => format!("{}-{}", base, quote),
+            SymbolFormat::Underscore => format!("{}_{}", base, quote),
+            SymbolFormat::Kraken => format!("{}{}", kraken_base(base), kraken_quote(quote)),
+            SymbolFormat::Bitfinex => {
+                let (base, quote) = (bitfinex_asset(base), bitfinex_asset(quote));
+                if base.len() > 3 || quote.len() > 3 {
+                    format!("t{}:{}", base, quote)
+                } else {
+                    format!("t{}{}", base, quote)
+                }
+            }
+        }
+    }
+}
+
+fn kraken_asset(code: &str) -> &str {
+    match code {
+        "BTC" => "XBT",
+        "DOGE" => "XDG",
+        other => other,
+    }
+}
+
+/// Legacy three-letter base assets carry an `X` (crypto) or `Z` (fiat) class prefix.
+fn kraken_base(code: &str) -> String {
+    let code = kraken_asset(code);
+    match code.len() {
+        3 if KRAKEN_FIAT.contains(&code) => format!("Z{}", code),
+        3 => format!("X{}", code),
+        _ => code.to_string(),
+    }
+}
+
+/// Only fiat quote assets carry a class prefix.
+fn kraken_quote(code: &str) -> String {
+    let code = kraken_asset(code);
+    if KRAKEN_FIAT.contains(&code) {
+        format!("Z{}", code)
+    } else {
+        code.to_string()
+    }
+}
+
+fn bitfinex_asset(code: &str) -> &str {
+    match code {
+        "USDT" => "UST",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::SymbolFormat;
+    use crate::common::instrument::Instrument;
+
+    #[test]
+    fn format_symbols() {
+        let eth_btc = Instrument::spot("ETH", "BTC");
+        assert_eq!("ethbtc", SymbolFormat::LowerConcat.format(&eth_btc));
+        assert_eq!("ETHBTC", SymbolFormat::UpperConcat.format(&eth_btc));
+        assert_eq!("ETH-BTC", SymbolFormat::Dash.format(&eth_btc));
+        assert_eq!("ETH_BTC", SymbolFormat::Underscore.format(&eth_btc));
+        assert_eq!("XETHXBT", SymbolFormat::Kraken.format(&eth_btc));
+        assert_eq!("tETHBTC", SymbolFormat::Bitfinex.format(&eth_btc));
+
+        let btc_usd = Instrument::spot("BTC", "USD");
+        assert_eq!("XXBTZUSD", SymbolFormat::Kraken.format(&btc_usd));
+        let eth_usdt = Instrument::spot("ETH", "USDT");
+        assert_eq!("tETHUST", SymbolFormat::Bitfinex.format(&eth_usdt));
+
+        // The formats are named in snake case in the configuration file.
+        let formats: HashMap<String, SymbolFormat> =
+            toml::from_str("a = \"upper_concat\"\nb = \"kraken\"").unwrap();
+        assert_eq!(SymbolFormat::UpperConcat, formats["a"]);
+        assert_eq!(SymbolFormat::Kraken, formats["b"]);
+    }
+}
@@ -0,0 +1,344 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use arrow_array::builder::{Float64Builder, StringBuilder};
+use arrow_array::{
+    ArrayRef, Float64Array, ListArray, RecordBatch, StringArray, StructArray,
+    TimestampNanosecondArray, UInt64Array,
+};
+use arrow_buffer::OffsetBuffer;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::common::instrument::Instrument;
+use crate::exchange::SymbolFormat;
+use crate::merger::MergedBook;
+use crate::proto;
+
+/// Write a record batch after this many summaries...
+const BATCH_ROWS: usize = 1024;
+/// ...or after this long, whichever comes first.
+const BATCH_INTERVAL: Duration = Duration::from_secs(1);
+const FILE_SUFFIX: &str = ".arrows";
+
+/// A merged order-book as it was broadcast.
+struct SummaryRecord {
+    /// The merger's sequence number of the summary.
+    /// Summaries which were missed because the writer fell behind leave gaps.
+    seq: u64,
+    /// Receive time of the order-book which updated the summary,
+    /// in nanoseconds since the Unix epoch.  Replayed summaries keep their recorded times.
+    timestamp_ns: i64,
+    summary: proto::Summary,
+}
+
+/// Records the merged order-book stream to Arrow IPC stream files,
+/// partitioned by symbol and hour:
+/// `<dir>/symbol=ETH-BTC/hour=2022-01-08T13/summaries-<time>.arrows`
+///
+/// The IPC stream format is used rather than the file format since it has no footer,
+/// so a file is readable up to its last complete batch even if the process is killed.
+pub fn start(dir: &Path, symbol: &Instrument, merged: broadcast::Receiver<MergedBook>) {
+    let (tx, rx) = mpsc::sync_channel(BATCH_ROWS * 4);
+    let partition_dir = dir.join(format!("symbol={}", SymbolFormat::Dash.format(symbol)));
+    let symbol = symbol.to_string();
+    tokio::spawn(receive_summaries(merged, tx));
+    std::thread::Builder::new()
+        .name("summary-history".to_string())
+        .spawn(move || write_summaries(partition_dir, symbol, rx))
+        .expect("Failed to start the summary history writer.");
+}
+
+/// Pass the merged order-books on to the writer.
+async fn receive_summaries(
+    mut merged: broadcast::Receiver<MergedBook>,
+    tx: SyncSender<SummaryRecord>,
+) {
+    loop {
+        match merged.recv().await {
+            Ok(merged_book) => {
+                let record = SummaryRecord {
+                    seq: merged_book.sequence,
+                    timestamp_ns: merged_book.timestamp_ns,
+                    summary: merged_book.summary,
+                };
+                match tx.try_send(record) {
+                    Ok(_) => { /* Pass */ }
+                    Err(TrySendError::Full(_)) => {
+                        warn!("Summary history writer is falling behind.  Dropped a merged order-book.")
+                    }
+                    Err(TrySendError::Disconnected(_)) => break,
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Summary history missed {} merged order-books.", missed);
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+struct HistoryFile {
+    writer: StreamWriter<BufWriter<File>>,
+    /// The hour partition, eg. `2022-01-08T13`.
+    hour: String,
+}
+
+fn write_summaries(partition_dir: PathBuf, symbol: String, rx: mpsc::Receiver<SummaryRecord>) {
+    let schema = summary_schema();
+    let mut file: Option<HistoryFile> = None;
+    let mut batch: Vec<SummaryRecord> = Vec::with_capacity(BATCH_ROWS);
+    let mut batch_started = Instant::now();
+
+    loop {
+        let record = match rx.recv_timeout(BATCH_INTERVAL) {
+            Ok(record) => record,
+            Err(RecvTimeoutError::Timeout) => {
+                flush(&schema, &symbol, &mut file, &mut batch);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let hour = hour_partition(record.timestamp_ns);
+        // A batch never spans two hours.
+        if file.as_ref().is_some_and(|f| f.hour != hour) {
+            flush(&schema, &symbol, &mut file, &mut batch);
+            if let Some(Err(err)) = file.take().map(|mut f| f.writer.finish()) {
+                error!("Failed to finish the summary history file:  {}", err);
+            }
+        }
+        if file.is_none() {
+            match create_file(&partition_dir, &schema, &hour) {
+                Ok(f) => file = Some(f),
+                Err(err) => {
+                    error!("Failed to create the summary history file:  {}", err);
+                    continue;
+                }
+            }
+        }
+
+        if batch.is_empty() {
+            batch_started = Instant::now();
+        }
+        batch.push(record);
+        if batch.len() >= BATCH_ROWS || batch_started.elapsed() >= BATCH_INTERVAL {
+            flush(&schema, &symbol, &mut file, &mut batch);
+        }
+    }
+    flush(&schema, &symbol, &mut file, &mut batch);
+    if let Some(Err(err)) = file.map(|mut f| f.writer.finish()) {
+        error!("Failed to finish the summary history file:  {}", err);
+    }
+}
+
+fn hour_partition(timestamp_ns: i64) -> String {
+    DateTime::<Utc>::from_timestamp_nanos(timestamp_ns)
+        .format("%Y-%m-%dT%H")
+        .to_string()
+}
+
+fn create_file(partition_dir: &Path, schema: &SchemaRef, hour: &str) -> io::Result<HistoryFile> {
+    let dir = partition_dir.join(format!("hour={}", hour));
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "summaries-{}{}",
+        Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+        FILE_SUFFIX
+    ));
+    info!("Recording merged order-books to {}", path.display());
+    let writer = StreamWriter::try_new(BufWriter::new(File::create(path)?), schema)
+        .map_err(io::Error::other)?;
+    Ok(HistoryFile {
+        writer,
+        hour: hour.to_string(),
+    })
+}
+
+/// Write the batch, and flush it to disk.
+fn flush(
+    schema: &SchemaRef,
+    symbol: &str,
+    file: &mut Option<HistoryFile>,
+    batch: &mut Vec<SummaryRecord>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let records = std::mem::take(batch);
+    let file = match file {
+        Some(file) => file,
+        None => return,
+    };
+    let result = summary_batch(schema, symbol, &records).and_then(|record_batch| {
+        file.writer.write(&record_batch)?;
+        file.writer.flush()
+    });
+    if let Err(err) = result {
+        error!(
+            "Failed to write {} merged order-books to the summary history:  {}",
+            records.len(),
+            err
+        );
+    }
+}
+
+fn level_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("amount", DataType::Float64, false),
+    ])
+}
+
+fn levels_field(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::List(Arc::new(Field::new(
+            "item",
+            DataType::Struct(level_fields()),
+            false,
+        ))),
+        false,
+    )
+}
+
+fn summary_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("seq", DataType::UInt64, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("spread", DataType::Float64, false),
+        levels_field("bids"),
+        levels_field("asks"),
+    ]))
+}
+
+fn summary_batch(
+    schema: &SchemaRef,
+    symbol: &str,
+    records: &[SummaryRecord],
+) -> Result<RecordBatch, ArrowError> {
+    let seq = UInt64Array::from_iter_values(records.iter().map(|r| r.seq));
+    let timestamp =
+        TimestampNanosecondArray::from_iter_values(records.iter().map(|r| r.timestamp_ns))
+            .with_timezone("UTC");
+    let symbols = StringArray::from_iter_values(records.iter().map(|_| symbol));
+    let spread = Float64Array::from_iter_values(records.iter().map(|r| r.summary.spread));
+    let bids = levels_array(records.iter().map(|r| &r.summary.bids))?;
+    let asks = levels_array(records.iter().map(|r| &r.summary.asks))?;
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(seq),
+        Arc::new(timestamp),
+        Arc::new(symbols),
+        Arc::new(spread),
+        Arc::new(bids),
+        Arc::new(asks),
+    ];
+    RecordBatch::try_new(schema.clone(), columns)
+}
+
+fn levels_array<'a>(
+    rows: impl Iterator<Item = &'a Vec<proto::Level>>,
+) -> Result<ListArray, ArrowError> {
+    let mut exchanges = StringBuilder::new();
+    let mut prices = Float64Builder::new();
+    let mut amounts = Float64Builder::new();
+    let mut lengths = vec![];
+    for levels in rows {
+        lengths.push(levels.len());
+        for level in levels {
+            exchanges.append_value(&level.exchange);
+            prices.append_value(level.price);
+            amounts.append_value(level.amount);
+        }
+    }
+    let levels = StructArray::try_new(
+        level_fields(),
+        vec![
+            Arc::new(exchanges.finish()),
+            Arc::new(prices.finish()),
+            Arc::new(amounts.finish()),
+        ],
+        None,
+    )?;
+    ListArray::try_new(
+        Arc::new(Field::new("item", DataType::Struct(level_fields()), false)),
+        OffsetBuffer::from_lengths(lengths),
+        Arc::new(levels),
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::{Array, ListArray, StructArray, UInt64Array};
+    use arrow_ipc::reader::StreamReader;
+    use arrow_ipc::writer::StreamWriter;
+
+    use super::{hour_partition, summary_batch, summary_schema, SummaryRecord};
+    use crate::common::fixtures::level;
+    use crate::proto;
+
+    #[test]
+    fn write_summary_batch() {
+        let records = vec![
+            SummaryRecord {
+                seq: 1,
+                timestamp_ns: 1641647673000000000,
+                summary: proto::Summary {
+                    spread: 0.5,
+                    bids: vec![level("binance", 10.0, 1.0), level("bitstamp", 9.5, 1.0)],
+                    asks: vec![level("bitstamp", 10.5, 1.0)],
+                },
+            },
+            SummaryRecord {
+                seq: 3,
+                timestamp_ns: 1641647674000000000,
+                summary: proto::Summary {
+                    spread: 1.0,
+                    bids: vec![level("binance", 10.0, 1.0)],
+                    asks: vec![level("binance", 11.0, 1.0)],
+                },
+            },
+        ];
+        let schema = summary_schema();
+        let batch = summary_batch(&schema, "ETH/BTC", &records).unwrap();
+
+        let mut buffer = vec![];
+        let mut writer = StreamWriter::try_new(&mut buffer, &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        let mut reader = StreamReader::try_new(buffer.as_slice(), None).unwrap();
+        let read = reader.next().unwrap().unwrap();
+        assert_eq!(2, read.num_rows());
+        let seq = read
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(vec![1, 3], seq.values().to_vec());
+        let bids = read.column(4).as_any().downcast_ref::<ListArray>().unwrap();
+        assert_eq!(2, bids.value_length(0));
+        assert_eq!(1, bids.value_length(1));
+        let second_bids = bids.value(1);
+        let second_bids = second_bids.as_any().downcast_ref::<StructArray>().unwrap();
+        assert_eq!(1, second_bids.len());
+
+        assert_eq!("2022-01-08T13", hour_partition(records[0].timestamp_ns));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::proto;
+
+/// The size of an order, in the base or the quote currency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantity {
+    Base(f64),
+    Quote(f64),
+}
+
+impl Quantity {
+    fn amount(&self) -> f64 {
+        match self {
+            Quantity::Base(amount) | Quantity::Quote(amount) => *amount,
+        }
+    }
+}
+
+/// Estimate the fill of a market order by walking the merged order-book from the best price.
+/// Filling level by level in price order is also the allocation across the exchanges
+/// which reaches the best combined price.
+/// Returns `None` if either side of the book is empty, since there is no mid price.
+pub fn estimate(
+    summary: &proto::Summary,
+    side: proto::Side,
+    quantity: Quantity,
+) -> Option<proto::MarketImpact> {
+    let mid = (summary.bids.first()?.price + summary.asks.first()?.price) / 2.0;
+    let levels = match side {
+        proto::Side::Buy => &summary.asks,
+        proto::Side::Sell => &summary.bids,
+    };
+
+    let mut remaining = quantity.amount();
+    let mut base_quantity = 0.0;
+    let mut quote_quantity = 0.0;
+    let mut worst_price = 0.0;
+    let mut allocations: Vec<proto::Allocation> = vec![];
+    for level in levels {
+        if remaining <= 0.0 {
+            break;
+        }
+        let base = match quantity {
+            Quantity::Base(_) => level.amount.min(remaining),
+            Quantity::Quote(_) => (level.price * level.amount).min(remaining) / level.price,
+        };
+        let quote = base * level.price;
+        remaining -= match quantity {
+            Quantity::Base(_) => base,
+            Quantity::Quote(_) => quote,
+        };
+        base_quantity += base;
+        quote_quantity += quote;
+        worst_price = level.price;
+
+        let allocation = match allocations
+            .iter_mut()
+            .find(|allocation| allocation.exchange == level.exchange)
+        {
+            Some(allocation) => allocation,
+            None => {
+                allocations.push(proto::Allocation {
+                    exchange: level.exchange.clone(),
+                    ..Default::default()
+                });
+                allocations.last_mut().unwrap()
+            }
+        };
+        allocation.base_quantity += base;
+        allocation.quote_quantity += quote;
+        allocation.vwap = allocation.quote_quantity / allocation.base_quantity;
+    }
+
+    let vwap = match base_quantity {
+        0.0 => 0.0,
+        _ => quote_quantity / base_quantity,
+    };
+    // Positive slippage is worse than the mid:  paying more, or receiving less.
+    let slippage_bps = match (side, base_quantity) {
+        (_, 0.0) => 0.0,
+        (proto::Side::Buy, _) => (vwap - mid) / mid * 10_000.0,
+        (proto::Side::Sell, _) => (mid - vwap) / mid * 10_000.0,
+    };
+    Some(proto::MarketImpact {
+        vwap,
+        mid,
+        slippage_bps,
+        worst_price,
+        base_quantity,
+        quote_quantity,
+        unfilled_quantity: remaining.max(0.0),
+        allocations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate, Quantity};
+    use crate::common::fixtures::level;
+    use crate::proto;
+
+    fn summary() -> proto::Summary {
+        proto::Summary {
+            spread: 2.0,
+            bids: vec![level("a", 99.0, 1.0), level("b", 98.0, 2.0)],
+            asks: vec![
+                level("b", 101.0, 1.0),
+                level("a", 102.0, 1.0),
+                level("b", 104.0, 2.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn estimate_buy_in_base() {
+        let impact = estimate(&summary(), proto::Side::Buy, Quantity::Base(3.0)).unwrap();
+        assert_eq!(100.0, impact.mid);
+        assert_eq!(3.0, impact.base_quantity);
+        assert_eq!(307.0, impact.quote_quantity);
+        assert_eq!(307.0 / 3.0, impact.vwap);
+        assert!((impact.slippage_bps - 233.33).abs() < 0.01);
+        assert_eq!(104.0, impact.worst_price);
+        assert_eq!(0.0, impact.unfilled_quantity);
+
+        let allocations: Vec<_> = impact
+            .allocations
+            .iter()
+            .map(|allocation| (allocation.exchange.as_str(), allocation.base_quantity))
+            .collect();
+        assert_eq!(vec![("b", 2.0), ("a", 1.0)], allocations);
+        assert_eq!(102.5, impact.allocations[0].vwap);
+    }
+
+    #[test]
+    fn estimate_sell_in_quote() {
+        let impact = estimate(&summary(), proto::Side::Sell, Quantity::Quote(148.0)).unwrap();
+        assert_eq!(1.5, impact.base_quantity);
+        assert_eq!(148.0, impact.quote_quantity);
+        assert_eq!(98.0, impact.worst_price);
+        assert!(impact.slippage_bps > 0.0);
+
+        // Deeper than the book.
+        let impact = estimate(&summary(), proto::Side::Sell, Quantity::Quote(400.0)).unwrap();
+        assert_eq!(3.0, impact.base_quantity);
+        assert_eq!(400.0 - 295.0, impact.unfilled_quantity);
+
+        let empty = proto::Summary {
+            asks: vec![],
+            ..summary()
+        };
+        assert_eq!(
+            None,
+            estimate(&empty, proto::Side::Sell, Quantity::Base(1.0))
+        );
+    }
+}
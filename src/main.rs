@@ -1,70 +1,350 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use log::info;
+use log::{error, info};
 use simplelog::SimpleLogger;
 use tokio::sync::{broadcast, mpsc};
 use tonic::transport::Server;
 
-use crate::common::{config::Config, OrderBook};
-use crate::exchange::Exchange;
+use crate::common::config::{Config, Mode};
+use crate::common::http::{HttpClient, ReqwestClient};
+use crate::common::OrderBook;
+use crate::exchange::metadata::{InstrumentInfo, InstrumentStatus};
+use crate::exchange::{Exchange, ExchangeRegistry};
 use crate::merger::OrderBookMerger;
+use crate::proto::orderbook_admin_server::OrderbookAdminServer;
 use crate::proto::orderbook_aggregator_server::OrderbookAggregatorServer;
+use crate::reader::{ReaderCommands, Subscription};
+use crate::recorder::FrameRecorder;
+use crate::rest::server::RestServer;
+use crate::rpc::admin::OrderbookAdminService;
+use crate::rpc::auth::AuthInterceptor;
 use crate::rpc::server::OrderbookAggregatorService;
+use crate::state::MarketState;
+use crate::websocket::server::WebsocketServer;
 
 mod proto {
     tonic::include_proto!("orderbook");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("orderbook_descriptor");
 }
+mod alerts;
+mod analytics;
+mod arbitrage;
 mod common;
 mod exchange;
+mod history;
+mod impact;
 mod merger;
+mod reader;
+mod recorder;
+mod replay;
+mod rest;
+mod routing;
 mod rpc;
+mod state;
+mod synthetic;
+mod websocket;
 
 #[tokio::main]
 async fn main() {
-    let config = Config::new();
+    let mut config = Config::new();
     SimpleLogger::init(config.log_level, simplelog::Config::default())
         .expect("Failed to initialize logging.");
 
-    // Start the exchange readers and receive a stream of order-books.
-    let order_books_rx = start_exchange_readers(&config.symbol.to_lowercase()).await;
+    // Look up the configured exchanges.
+    let exchanges = ExchangeRegistry::default()
+        .resolve(&config.exchanges)
+        .unwrap_or_else(|err| {
+            error!("{}", err);
+            std::process::exit(1);
+        });
 
-    // Start the order-book merger coroutine.
+    let exchange_names: Vec<_> = exchanges.iter().map(|exchange| exchange.name()).collect();
+    let max_depths: HashMap<_, _> = exchanges
+        .iter()
+        .map(|exchange| (exchange.name(), exchange.capabilities().max_depth))
+        .collect();
     let (merged_tx, _) = broadcast::channel(100);
+    let (exchange_books_tx, _) = broadcast::channel(100);
+    let state = Arc::new(MarketState::default());
+    tokio::spawn(state::detect_stale_exchanges(state.clone()));
+
+    // Optionally record the merged order-books.
+    if let Some(dir) = &config.history_dir {
+        history::start(dir, &config.symbol, merged_tx.subscribe());
+    }
+
+    // Receive a stream of order-books from the exchanges, or from a recording.
+    info!("Merging {} order-books.", config.symbol);
+    let (order_books_rx, readers) = match &config.mode {
+        Mode::Live => start_live(&config, exchanges, state.clone()).await,
+        Mode::Replay { paths, speed } => {
+            let files = replay::frame_files(paths).unwrap_or_else(|err| {
+                error!("Failed to list the recording files:  {}", err);
+                std::process::exit(1);
+            });
+            // Recorded frames are replayed without checking the instruments' metadata.
+            let subscriptions = exchanges
+                .into_iter()
+                .map(|exchange| {
+                    let subscription = match config.synthetic_instrument(exchange.as_ref()) {
+                        None => Subscription::Symbol(InstrumentInfo::unchecked(
+                            &config.exchange_symbol(exchange.as_ref()),
+                        )),
+                        Some(synthetic) => {
+                            let legs = synthetic.legs.clone().map(|leg| {
+                                InstrumentInfo::unchecked(
+                                    &config.instrument_symbol(exchange.as_ref(), &leg),
+                                )
+                            });
+                            Subscription::Synthetic(Arc::new(synthetic), legs)
+                        }
+                    };
+                    (exchange, subscription)
+                })
+                .collect();
+            (replay::start(files, subscriptions, *speed), HashMap::new())
+        }
+    };
+
+    // Start the order-book merger coroutine.
     let mtx = merged_tx.clone();
+    let merger_state = state.clone();
+    let merger_exchange_books_tx = exchange_books_tx.clone();
+    let (merger_commands_tx, merger_commands_rx) = mpsc::channel(10);
     tokio::spawn(async move {
-        OrderBookMerger::default().start(mtx, order_books_rx).await;
+        OrderBookMerger::new(merger_state, merger_exchange_books_tx, max_depths)
+            .start(mtx, order_books_rx, merger_commands_rx)
+            .await;
     });
 
+    // Optionally evaluate the alert rules, and post the alerts to a webhook.
+    let alert_settings = &config.settings.alerts;
+    if !alert_settings.rules.is_empty() {
+        info!("Evaluating {} alert rules.", alert_settings.rules.len());
+        tokio::spawn(alerts::evaluate_alerts(
+            alert_settings.rules.clone(),
+            state.clone(),
+            merged_tx.subscribe(),
+        ));
+        if let Some(webhook) = &alert_settings.webhook {
+            info!("Posting alerts to {}.", webhook);
+            tokio::spawn(alerts::send_to_webhook(
+                webhook.clone(),
+                Box::new(ReqwestClient::with_timeout(alerts::WEBHOOK_TIMEOUT)),
+                state.subscribe_alerts(),
+            ));
+        }
+    }
+
+    // Optionally start the websocket JSON service.
+    if let Some(ws_port) = config.ws_port {
+        let ws_addr = SocketAddr::new(config.host, ws_port);
+        info!("Starting websocket server on {}...", ws_addr);
+        let ws_server = WebsocketServer::new(config.symbol.clone(), merged_tx.clone());
+        tokio::spawn(async move {
+            if let Err(err) = ws_server.serve(ws_addr).await {
+                error!("Websocket server failed:  {}", err);
+            }
+        });
+    }
+
+    // Optionally start the HTTP service.
+    if let Some(http_port) = config.http_port {
+        let http_addr = SocketAddr::new(config.host, http_port);
+        info!("Starting HTTP server on {}...", http_addr);
+        let rest_server = RestServer::new(
+            config.symbol.clone(),
+            state.clone(),
+            config.min_live_exchanges,
+        );
+        tokio::spawn(async move {
+            if let Err(err) = rest_server.serve(http_addr).await {
+                error!("HTTP server failed:  {}", err);
+            }
+        });
+    }
+
     // Start the gRPC service.
     info!("Staring gRPC server on {}:{}...", config.host, config.port);
-    let orderbook_aggregator_service = OrderbookAggregatorService::new(merged_tx);
-    let service = OrderbookAggregatorServer::new(orderbook_aggregator_service);
+    let orderbook_aggregator_service = OrderbookAggregatorService::new(
+        config.symbol.clone(),
+        exchange_names.clone(),
+        merged_tx,
+        exchange_books_tx,
+        state.clone(),
+        config.stream_limits,
+        config.exchange_terms(),
+    );
+    if let Some(credentials) = &config.credentials {
+        info!(
+            "Authenticating gRPC clients with {} tokens.",
+            credentials.clients.len()
+        );
+    }
+    let authenticated = config.credentials.is_some();
+    let interceptor = AuthInterceptor::new(config.credentials.take());
+    let service = OrderbookAggregatorServer::with_interceptor(
+        orderbook_aggregator_service,
+        interceptor.clone(),
+    );
+    // The admin service is only served to authenticated admins.
+    let admin_service = if authenticated {
+        let orderbook_admin_service = OrderbookAdminService::new(
+            config.symbol.clone(),
+            exchange_names,
+            readers,
+            merger_commands_tx,
+            state.clone(),
+        );
+        Some(OrderbookAdminServer::with_interceptor(
+            orderbook_admin_service,
+            interceptor,
+        ))
+    } else {
+        info!("Not serving the admin service without --credentials.");
+        None
+    };
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(rpc::health::report_exchange_liveness(
+        health_reporter,
+        state,
+        config.min_live_exchanges,
+    ));
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(
+            tonic_health::proto::GRPC_HEALTH_V1_FILE_DESCRIPTOR_SET,
+        )
+        .build()
+        .expect("Failed to build the gRPC reflection service.");
     let addr = SocketAddr::new(config.host, config.port);
-    Server::builder()
+    let mut server = Server::builder();
+    if let Some(tls) = &config.tls {
+        info!(
+            "Serving gRPC over TLS{}.",
+            if tls.client_ca.is_some() {
+                " with client certificates"
+            } else {
+                ""
+            }
+        );
+        let tls_config = tls.server_tls_config().unwrap_or_else(|err| {
+            error!("Failed to load the TLS certificates:  {}", err);
+            std::process::exit(1);
+        });
+        server = server
+            .tls_config(tls_config)
+            .expect("Failed to configure TLS.");
+    }
+    server
         .add_service(service)
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_optional_service(admin_service)
         .serve(addr)
         .await
         .expect("Failed to start the gRPC server.");
 }
 
-/// Start the exchange websocket readers.
-/// Returns a live stream of order-books.
-async fn start_exchange_readers(trading_symbol: &str) -> mpsc::Receiver<OrderBook> {
-    let (tx, rx) = mpsc::channel(100);
-
-    let binance_sender = tx.clone();
-    let symbol = trading_symbol.to_string();
-    let _binance_stream = tokio::spawn(async move {
-        exchange::binance::Binance::start(&symbol, binance_sender)
-            .await
-            .expect("Binance stream failed to start.");
-    });
-    let bitstamp_sender = tx.clone();
-    let symbol = trading_symbol.to_string();
-    let _bitstamp_stream = tokio::spawn(async move {
-        exchange::bitstamp::Bitstamp::start(&symbol, bitstamp_sender)
-            .await
-            .expect("Bitstamp stream failed to start.");
+/// Start reading from the exchanges' websocket streams.
+async fn start_live(
+    config: &Config,
+    exchanges: Vec<Arc<dyn Exchange>>,
+    state: Arc<MarketState>,
+) -> (mpsc::Receiver<OrderBook>, ReaderCommands) {
+    // Only read from the exchanges which list the symbol.
+    let subscriptions = load_instruments(config, &ReqwestClient::default(), exchanges).await;
+    if subscriptions.is_empty() {
+        error!("None of the configured exchanges trade {}.", config.symbol);
+        std::process::exit(1);
+    }
+
+    // Optionally record the raw exchange frames.
+    let recorder = config.record_dir.as_ref().map(|dir| {
+        FrameRecorder::start(dir).unwrap_or_else(|err| {
+            error!("Failed to start recording to {}:  {}", dir.display(), err);
+            std::process::exit(1);
+        })
     });
-    return rx;
+
+    reader::start_exchange_readers(subscriptions, recorder, state)
+}
+
+/// Fetch each exchange's metadata for the configured symbol,
+/// or for both legs of the exchange's synthetic instrument.
+/// Exchanges which do not list the symbols, or are not trading them, are refused.
+async fn load_instruments(
+    config: &Config,
+    http: &dyn HttpClient,
+    exchanges: Vec<Arc<dyn Exchange>>,
+) -> Vec<(Arc<dyn Exchange>, Subscription)> {
+    let mut subscriptions = vec![];
+    for exchange in exchanges {
+        let subscription = match config.synthetic_instrument(exchange.as_ref()) {
+            None => {
+                let symbol = config.exchange_symbol(exchange.as_ref());
+                load_instrument(config, http, exchange.as_ref(), &symbol)
+                    .await
+                    .map(Subscription::Symbol)
+            }
+            Some(synthetic) => {
+                info!("{} builds {}.", exchange.name(), synthetic);
+                let [first, second] = &synthetic.legs;
+                let first = config.instrument_symbol(exchange.as_ref(), first);
+                let second = config.instrument_symbol(exchange.as_ref(), second);
+                match (
+                    load_instrument(config, http, exchange.as_ref(), &first).await,
+                    load_instrument(config, http, exchange.as_ref(), &second).await,
+                ) {
+                    (Some(first), Some(second)) => Some(Subscription::Synthetic(
+                        Arc::new(synthetic),
+                        [first, second],
+                    )),
+                    _ => None,
+                }
+            }
+        };
+        if let Some(subscription) = subscription {
+            subscriptions.push((exchange, subscription));
+        }
+    }
+    subscriptions
+}
+
+/// Fetch the exchange's metadata for the symbol.
+/// Returns `None` if the exchange is refused.
+async fn load_instrument(
+    config: &Config,
+    http: &dyn HttpClient,
+    exchange: &dyn Exchange,
+    symbol: &str,
+) -> Option<InstrumentInfo> {
+    let rest_endpoint = config.rest_endpoint(exchange);
+    match exchange.instrument_info(http, &rest_endpoint, symbol).await {
+        Err(err) => error!(
+            "Refusing {}:  failed to load the instrument metadata of '{}':  {}",
+            exchange.name(),
+            symbol,
+            err
+        ),
+        Ok(None) => error!(
+            "Refusing {}:  the exchange does not list '{}'.",
+            exchange.name(),
+            symbol
+        ),
+        Ok(Some(info)) if info.status != InstrumentStatus::Trading => error!(
+            "Refusing {}:  '{}' is not trading.",
+            exchange.name(),
+            symbol
+        ),
+        Ok(Some(info)) => {
+            info!("{} instrument:  {}", exchange.name(), info);
+            return Some(info);
+        }
+    }
+    None
 }
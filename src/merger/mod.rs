@@ -1,69 +1,421 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use itertools::Itertools;
-use log::debug;
+use log::{debug, info};
 use tokio::sync::{broadcast, mpsc};
 
+use crate::common::now_ns;
+use crate::routing::ExchangeTerms;
+use crate::state::{MarketState, SequencedBook};
 use crate::{proto, OrderBook};
 
-/// The number of order book entries to keep for processing.
+/// The number of levels of each side of the merged order-book.
 /// Set it to 10 since that is the output of the gRPC stream.
+/// The exchanges' order-books keep as many levels as their adapters publish.
 const NUM_ORDER_BOOK_ENTRIES: usize = 10;
 
-#[derive(Default)]
+/// Operator commands which change what is merged.
+#[derive(Debug)]
+pub enum MergerCommand {
+    /// Drop the exchange's order-book, and ignore its order-books until enabled.
+    Disable(&'static str),
+    Enable(&'static str),
+    /// Drop the exchange's order-book until it sends a new one.
+    ClearBook(&'static str),
+}
+
+/// A merged order-book, published together with the exchanges' order-books it was merged from,
+/// so that clients which re-merge them, eg. with fees applied, use the same snapshot.
+#[derive(Clone, Debug)]
+pub struct MergedBook {
+    /// The number of order-books merged so far, starting at one.
+    pub sequence: u64,
+    /// Receive time of the order-book which updated the merged order-book,
+    /// or the time of the command which changed it, in nanoseconds since the Unix epoch.
+    pub timestamp_ns: i64,
+    pub summary: proto::Summary,
+    /// Unlike the exchanges' latest order-books, they leave out disabled and cleared exchanges.
+    /// `<exchange-name> => <order-book>`
+    pub order_books: Arc<HashMap<&'static str, OrderBook>>,
+}
+
+impl MergedBook {
+    /// The merged order-book of the given exchanges, or of every exchange.
+    /// Their order-books are merged again, so that it is as deep as the merged order-book.
+    pub fn of_exchanges(&self, exchanges: Option<&HashSet<String>>) -> proto::Summary {
+        match exchanges {
+            None => self.summary.clone(),
+            Some(_) => merge_levels(
+                self.order_books_of(exchanges),
+                NUM_ORDER_BOOK_ENTRIES,
+                |_, level| level.clone(),
+            ),
+        }
+    }
+
+    /// Every level of the given exchanges' order-books, or of every exchange's, merged.
+    /// Each exchange's order-book is as deep as its adapter publishes.
+    pub fn every_level_of(&self, exchanges: Option<&HashSet<String>>) -> proto::Summary {
+        merge_levels(self.order_books_of(exchanges), usize::MAX, |_, level| {
+            level.clone()
+        })
+    }
+
+    /// Merge the given exchanges' order-books, or every exchange's, with the taker fees
+    /// applied to their prices:  bids are lowered and asks are raised,
+    /// so that they rank by what they would execute at.
+    pub fn fee_adjusted(
+        &self,
+        exchanges: Option<&HashSet<String>>,
+        exchange_terms: &HashMap<String, ExchangeTerms>,
+    ) -> proto::Summary {
+        merge_levels(
+            self.order_books_of(exchanges),
+            NUM_ORDER_BOOK_ENTRIES,
+            |side, level| {
+                let terms = exchange_terms
+                    .get(&level.exchange)
+                    .copied()
+                    .unwrap_or_default();
+                proto::Level {
+                    price: terms.fee_adjusted_price(side, level.price),
+                    raw_price: level.price,
+                    ..level.clone()
+                }
+            },
+        )
+    }
+
+    fn order_books_of<'a>(
+        &'a self,
+        exchanges: Option<&'a HashSet<String>>,
+    ) -> impl Iterator<Item = &'a OrderBook> + Clone {
+        self.order_books.values().filter(move |order_book| {
+            exchanges.is_none_or(|exchanges| exchanges.contains(order_book.exchange))
+        })
+    }
+}
+
 pub struct OrderBookMerger {
     /// The up-to-date state of the exchanges' order-books.
     /// `<exchange-name> => <order-book>`
     pub order_books: HashMap<&'static str, OrderBook>,
+    /// Exchanges whose order-books are ignored.
+    disabled: HashSet<&'static str>,
+    /// Where the latest merged order-book and exchange updates are published.
+    state: Arc<MarketState>,
+    /// Each exchange's order-books are also published on this broadcast channel.
+    exchange_books_tx: broadcast::Sender<SequencedBook>,
+    /// The sequence number of the latest merged order-book.
+    sequence: u64,
+    /// The number of levels of each side of the exchanges' order-books to keep,
+    /// from their adapters' capabilities.
+    /// `<exchange-name> => <max-depth>`
+    max_depths: HashMap<&'static str, usize>,
 }
 
 impl OrderBookMerger {
+    pub fn new(
+        state: Arc<MarketState>,
+        exchange_books_tx: broadcast::Sender<SequencedBook>,
+        max_depths: HashMap<&'static str, usize>,
+    ) -> Self {
+        Self {
+            order_books: HashMap::new(),
+            disabled: HashSet::new(),
+            state,
+            exchange_books_tx,
+            sequence: 0,
+            max_depths,
+        }
+    }
+
     /// Read from the order-book stream and merge them as they arrive.
     /// Send the merged order books out on the broadcast channel.
     pub async fn start(
         &mut self,
-        tx: broadcast::Sender<proto::Summary>,
+        tx: broadcast::Sender<MergedBook>,
         mut rx: mpsc::Receiver<OrderBook>,
+        mut commands: mpsc::Receiver<MergerCommand>,
     ) {
-        while let Some(mut order_book) = rx.recv().await {
-            let exchange_name = order_book.exchange.clone();
-            // Truncate the bids and asks.
-            order_book.bids.truncate(NUM_ORDER_BOOK_ENTRIES);
-            order_book.asks.truncate(NUM_ORDER_BOOK_ENTRIES);
-
-            // Update the order-book state.
-            self.order_books.insert(exchange_name, order_book);
-            debug!("{:?}", self.order_books.get(exchange_name));
+        loop {
+            let timestamp_ns = tokio::select! {
+                order_book = rx.recv() => match order_book {
+                    Some(order_book) => {
+                        let received_ns = order_book.received_ns;
+                        if !self.update(order_book) {
+                            continue;
+                        }
+                        received_ns
+                    }
+                    None => break,
+                },
+                Some(command) = commands.recv() => {
+                    info!("Merger command:  {:?}", command);
+                    match command {
+                        MergerCommand::Disable(exchange) => {
+                            self.disabled.insert(exchange);
+                            self.clear(exchange);
+                        }
+                        MergerCommand::Enable(exchange) => {
+                            self.disabled.remove(exchange);
+                        }
+                        MergerCommand::ClearBook(exchange) => self.clear(exchange),
+                    }
+                    now_ns()
+                }
+            };
 
             // Merge the order books and send to the broadcast channel.
-            let bids: Vec<proto::Level> = self
-                .order_books
-                .values()
-                .fold(vec![], |mut acc, v| {
-                    acc.append(&mut v.bids.clone());
-                    acc
-                })
-                .into_iter()
-                // Sort bids by descending price.
-                .sorted_by(|a, b| b.price.partial_cmp(&a.price).unwrap())
-                .take(NUM_ORDER_BOOK_ENTRIES)
-                .collect();
-            let asks: Vec<proto::Level> = self
-                .order_books
-                .values()
-                .fold(vec![], |mut acc, v| {
-                    acc.append(&mut v.asks.clone());
-                    acc
-                })
-                .into_iter()
-                // Sort asks by ascending price.
-                .sorted_by(|a, b| a.price.partial_cmp(&b.price).unwrap())
-                .take(NUM_ORDER_BOOK_ENTRIES)
-                .collect();
-            let spread = asks.first().unwrap().price - bids.first().unwrap().price;
-
-            let merged_books = proto::Summary { spread, bids, asks };
-            tx.send(merged_books).unwrap_or(0);
+            let merged_book = self.merge(timestamp_ns);
+            self.state.set_latest(merged_book.clone());
+            tx.send(merged_book).unwrap_or(0);
+        }
+    }
+
+    /// Update the exchange's order-book.
+    /// Returns whether it was updated, ie. the exchange is not disabled.
+    fn update(&mut self, mut order_book: OrderBook) -> bool {
+        let exchange_name = order_book.exchange;
+        if self.disabled.contains(exchange_name) {
+            debug!("Ignoring an order-book of disabled {}.", exchange_name);
+            return false;
+        }
+        // Truncate the bids and asks to the depth of the exchange's adapter.
+        let max_depth = self
+            .max_depths
+            .get(exchange_name)
+            .copied()
+            .unwrap_or(NUM_ORDER_BOOK_ENTRIES);
+        order_book.bids.truncate(max_depth);
+        order_book.asks.truncate(max_depth);
+
+        // Update the order-book state.
+        let sequence = self.state.book_updated(exchange_name);
+        let exchange_book = SequencedBook {
+            sequence,
+            order_book: order_book.clone(),
+        };
+        self.state.set_exchange_book(exchange_book.clone());
+        self.exchange_books_tx.send(exchange_book).unwrap_or(0);
+        self.order_books.insert(exchange_name, order_book);
+        debug!("{:?}", self.order_books.get(exchange_name));
+        true
+    }
+
+    /// Drop the exchange's order-book, and publish an empty one in its place,
+    /// so that the streams of the exchange's order-books stop serving the dropped one.
+    fn clear(&mut self, exchange: &'static str) {
+        self.order_books.remove(exchange);
+        let exchange_book = self.state.clear_exchange_book(exchange);
+        self.exchange_books_tx.send(exchange_book).unwrap_or(0);
+    }
+
+    /// Merge the exchanges' order-books.
+    fn merge(&mut self, timestamp_ns: i64) -> MergedBook {
+        self.sequence += 1;
+        MergedBook {
+            sequence: self.sequence,
+            timestamp_ns,
+            summary: merge_levels(
+                self.order_books.values(),
+                NUM_ORDER_BOOK_ENTRIES,
+                |_, level| level.clone(),
+            ),
+            order_books: Arc::new(self.order_books.clone()),
         }
     }
 }
+
+/// Merge the top `depth` levels of the exchanges' order-books, after mapping each level.
+/// Bids are mapped as taken by a sell, and asks as taken by a buy.
+fn merge_levels<'a, I, F>(order_books: I, depth: usize, map_level: F) -> proto::Summary
+where
+    I: Iterator<Item = &'a OrderBook> + Clone,
+    F: Fn(proto::Side, &proto::Level) -> proto::Level,
+{
+    let bids: Vec<proto::Level> = order_books
+        .clone()
+        .flat_map(|v| v.bids.iter())
+        .map(|level| map_level(proto::Side::Sell, level))
+        // Sort bids by descending price.
+        .sorted_by(|a, b| b.price.partial_cmp(&a.price).unwrap())
+        .take(depth)
+        .collect();
+    let asks: Vec<proto::Level> = order_books
+        .flat_map(|v| v.asks.iter())
+        .map(|level| map_level(proto::Side::Buy, level))
+        // Sort asks by ascending price.
+        .sorted_by(|a, b| a.price.partial_cmp(&b.price).unwrap())
+        .take(depth)
+        .collect();
+    // A side is empty when every exchange's book has been cleared or disabled.
+    let spread = match (bids.first(), asks.first()) {
+        (Some(bid), Some(ask)) => ask.price - bid.price,
+        _ => 0.0,
+    };
+
+    proto::Summary { spread, bids, asks }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    use tokio::sync::{broadcast, mpsc};
+
+    use super::{MergerCommand, OrderBookMerger};
+    use crate::common::fixtures::{order_book, top_of_book};
+    use crate::routing::ExchangeTerms;
+    use crate::state::MarketState;
+
+    #[tokio::test]
+    async fn disable_and_clear_books() {
+        let (merged_tx, mut merged_rx) = broadcast::channel(10);
+        let (books_tx, books_rx) = mpsc::channel(10);
+        let (commands_tx, commands_rx) = mpsc::channel(10);
+        let (exchange_books_tx, mut exchange_books_rx) = broadcast::channel(10);
+        let state = Arc::new(MarketState::default());
+        let mut merger = OrderBookMerger::new(state.clone(), exchange_books_tx, HashMap::new());
+        tokio::spawn(async move { merger.start(merged_tx, books_rx, commands_rx).await });
+
+        let mut a = top_of_book("a", 10.0, 11.0);
+        a.received_ns = 1;
+        books_tx.send(a).await.unwrap();
+        books_tx.send(top_of_book("b", 10.5, 11.5)).await.unwrap();
+        // Merged order-books are numbered and timed by the order-books which updated them.
+        let merged_book = merged_rx.recv().await.unwrap();
+        assert_eq!((1, 1), (merged_book.sequence, merged_book.timestamp_ns));
+        assert_eq!(0.5, merged_rx.recv().await.unwrap().summary.spread);
+
+        commands_tx.send(MergerCommand::Disable("a")).await.unwrap();
+        assert_eq!(1.0, merged_rx.recv().await.unwrap().summary.spread);
+        // Order-books of disabled exchanges are ignored.
+        books_tx.send(top_of_book("a", 10.0, 11.0)).await.unwrap();
+        commands_tx
+            .send(MergerCommand::ClearBook("b"))
+            .await
+            .unwrap();
+        let merged = merged_rx.recv().await.unwrap().summary;
+        assert!(merged.bids.is_empty() && merged.asks.is_empty());
+        assert_eq!(0.0, merged.spread);
+        // The exchanges' order-books are replaced by empty ones, numbered after them.
+        let sequences: Vec<_> = std::iter::from_fn(|| exchange_books_rx.try_recv().ok())
+            .map(|book| {
+                (
+                    book.order_book.exchange,
+                    book.sequence,
+                    book.order_book.bids.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![("a", 1, 1), ("b", 1, 1), ("a", 2, 0), ("b", 2, 0)],
+            sequences
+        );
+        let b = state.exchange_book("b").unwrap();
+        assert!(b.order_book.bids.is_empty() && b.order_book.asks.is_empty());
+
+        commands_tx.send(MergerCommand::Enable("a")).await.unwrap();
+        merged_rx.recv().await.unwrap();
+        books_tx.send(top_of_book("a", 10.0, 11.0)).await.unwrap();
+        assert_eq!(1.0, merged_rx.recv().await.unwrap().summary.spread);
+    }
+
+    #[test]
+    fn merge_books_of_exchanges() {
+        let mut merger = OrderBookMerger::new(
+            Arc::new(MarketState::default()),
+            broadcast::channel(10).0,
+            HashMap::new(),
+        );
+        let mut a = top_of_book("a", 100.0, 101.0);
+        a.bids = vec![a.bids[0].clone(); 10];
+        merger.order_books.insert("a", a);
+        merger
+            .order_books
+            .insert("b", top_of_book("b", 99.0, 102.0));
+        let merged_book = merger.merge(0);
+        // b's bid is not in the top levels of every exchange.
+        assert!(merged_book
+            .summary
+            .bids
+            .iter()
+            .all(|level| level.exchange == "a"));
+
+        let of_b = merged_book.of_exchanges(Some(&HashSet::from(["b".to_string()])));
+        assert_eq!(
+            vec![99.0],
+            of_b.bids.iter().map(|l| l.price).collect::<Vec<_>>()
+        );
+        assert_eq!(3.0, of_b.spread);
+        assert_eq!(merged_book.summary, merged_book.of_exchanges(None));
+        // Every level of each exchange.
+        assert_eq!(11, merged_book.every_level_of(None).bids.len());
+    }
+
+    #[test]
+    fn keep_the_depth_of_each_exchange() {
+        let mut merger = OrderBookMerger::new(
+            Arc::new(MarketState::default()),
+            broadcast::channel(10).0,
+            HashMap::from([("a", 12)]),
+        );
+        let levels: Vec<_> = (0..15).map(|i| (100.0 - i as f64, 1.0)).collect();
+        merger.update(order_book("a", &levels, &[(101.0, 1.0)]));
+        merger.update(order_book("b", &levels, &[(101.0, 1.0)]));
+
+        // a keeps the depth of its adapter, and b the depth of the merged order-book.
+        assert_eq!(12, merger.order_books["a"].bids.len());
+        assert_eq!(10, merger.order_books["b"].bids.len());
+        let merged_book = merger.merge(0);
+        assert_eq!(10, merged_book.summary.bids.len());
+        assert_eq!(22, merged_book.every_level_of(None).bids.len());
+    }
+
+    #[test]
+    fn merge_fee_adjusted_books() {
+        let fee = |taker_fee_bps| ExchangeTerms {
+            taker_fee_bps,
+            min_order_size: 0.0,
+            synthetic: false,
+        };
+        let mut merger = OrderBookMerger::new(
+            Arc::new(MarketState::default()),
+            broadcast::channel(10).0,
+            HashMap::new(),
+        );
+        merger
+            .order_books
+            .insert("a", top_of_book("a", 100.0, 101.0));
+        merger
+            .order_books
+            .insert("b", top_of_book("b", 99.5, 101.5));
+
+        let merged_book = merger.merge(0);
+        let merged = &merged_book.summary;
+        assert_eq!(
+            ("a", "a"),
+            (&*merged.bids[0].exchange, &*merged.asks[0].exchange)
+        );
+
+        // The fee of a makes b's prices better.
+        let exchange_terms =
+            HashMap::from([("a".to_string(), fee(100.0)), ("b".to_string(), fee(0.0))]);
+        let merged = merged_book.fee_adjusted(None, &exchange_terms);
+        let bids: Vec<_> = merged
+            .bids
+            .iter()
+            .map(|level| (level.exchange.as_str(), level.price, level.raw_price))
+            .collect();
+        assert_eq!(vec![("b", 99.5, 99.5), ("a", 99.0, 100.0)], bids);
+        assert_eq!(
+            ("b", 101.5),
+            (&*merged.asks[0].exchange, merged.asks[0].price)
+        );
+        assert_eq!(102.01, merged.asks[1].price);
+        assert_eq!(2.0, merged.spread);
+    }
+}
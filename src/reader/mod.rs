@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use tokio::sync::mpsc;
+
+use crate::common::OrderBook;
+use crate::exchange::metadata::InstrumentInfo;
+use crate::exchange::{Exchange, ExchangeError};
+use crate::recorder::FrameRecorder;
+use crate::state::{ConnectionStatus, MarketState};
+use crate::synthetic::{self, SyntheticInstrument};
+
+/// The delay before the first reconnection attempt.
+/// It doubles with each consecutive failure, up to [MAX_RECONNECT_DELAY].
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Exchanges whose stream fails this many times in a row, without
+/// an order-book in between, are quarantined.
+const QUARANTINE_AFTER_FAILURES: u32 = 5;
+/// How long a quarantined exchange waits before reconnecting.
+const QUARANTINE_DURATION: Duration = Duration::from_secs(300);
+
+/// Operator commands to an exchange reader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReaderCommand {
+    /// Disconnect, and stay disconnected until enabled, for the given reason.
+    Disable(String),
+    Enable,
+    /// Reconnect and subscribe again.
+    Resubscribe,
+}
+
+/// Why an exchange reader stopped reading.
+enum Interruption {
+    Failed(String),
+    Disabled(String),
+    Resubscribe,
+}
+
+/// The order-book streams an exchange reader subscribes to.
+#[derive(Clone, Debug)]
+pub enum Subscription {
+    /// The symbol's order-book stream.
+    Symbol(InstrumentInfo),
+    /// The order-book streams of the synthetic instrument's legs.
+    Synthetic(Arc<SyntheticInstrument>, [InstrumentInfo; 2]),
+}
+
+impl Subscription {
+    /// Connect and read from the exchange's websocket streams.
+    async fn start(
+        &self,
+        exchange: &dyn Exchange,
+        recorder: Option<&FrameRecorder>,
+        state: &MarketState,
+        sink: mpsc::Sender<OrderBook>,
+    ) -> Result<(), ExchangeError> {
+        match self {
+            Subscription::Symbol(instrument) => {
+                exchange.start(instrument, recorder, state, sink).await
+            }
+            Subscription::Synthetic(instrument, legs) => {
+                synthetic::start(exchange, instrument.clone(), legs, recorder, state, sink).await
+            }
+        }
+    }
+}
+
+impl fmt::Display for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subscription::Symbol(instrument) => write!(f, "'{}'", instrument.symbol),
+            Subscription::Synthetic(_, [first, second]) => {
+                write!(f, "'{}' and '{}'", first.symbol, second.symbol)
+            }
+        }
+    }
+}
+
+/// `<exchange-name> => <command-sender>`
+pub type ReaderCommands = HashMap<&'static str, mpsc::Sender<ReaderCommand>>;
+
+/// Start the exchange websocket readers.
+/// Readers whose stream fails are reconnected.
+/// Returns a live stream of order-books, and the readers' command channels.
+pub fn start_exchange_readers(
+    subscriptions: Vec<(Arc<dyn Exchange>, Subscription)>,
+    recorder: Option<FrameRecorder>,
+    state: Arc<MarketState>,
+) -> (mpsc::Receiver<OrderBook>, ReaderCommands) {
+    let (tx, rx) = mpsc::channel(100);
+    let mut commands = HashMap::new();
+
+    for (exchange, subscription) in subscriptions {
+        info!(
+            "Starting {} reader for {} ({}).",
+            exchange.name(),
+            subscription,
+            exchange.capabilities()
+        );
+        let (command_tx, command_rx) = mpsc::channel(10);
+        commands.insert(exchange.name(), command_tx);
+        tokio::spawn(supervise_reader(
+            exchange,
+            subscription,
+            recorder.clone(),
+            state.clone(),
+            tx.clone(),
+            command_rx,
+        ));
+    }
+    (rx, commands)
+}
+
+/// The next command.  Never returns once the command channel is closed.
+async fn next_command(commands: &mut mpsc::Receiver<ReaderCommand>) -> ReaderCommand {
+    match commands.recv().await {
+        Some(command) => command,
+        None => std::future::pending().await,
+    }
+}
+
+/// Stay disconnected until enabled.
+async fn disable(
+    name: &'static str,
+    reason: String,
+    state: &MarketState,
+    commands: &mut mpsc::Receiver<ReaderCommand>,
+) {
+    info!("{} reader disabled:  {}", name, reason);
+    state.set_status(name, ConnectionStatus::Disabled, reason, None);
+    while next_command(commands).await != ReaderCommand::Enable {}
+    info!("{} reader enabled.", name);
+}
+
+/// Read from the exchange's stream, and reconnect whenever it fails.
+/// Commands interrupt the reader whether it is reading or waiting to reconnect.
+async fn supervise_reader(
+    exchange: Arc<dyn Exchange>,
+    subscription: Subscription,
+    recorder: Option<FrameRecorder>,
+    state: Arc<MarketState>,
+    sender: mpsc::Sender<OrderBook>,
+    mut commands: mpsc::Receiver<ReaderCommand>,
+) {
+    let name = exchange.name();
+    let sequence = |state: &MarketState| state.exchange(name).map_or(0, |s| s.sequence);
+    let mut failures = 0;
+    loop {
+        state.set_status(
+            name,
+            ConnectionStatus::Connecting,
+            format!("Connecting to {}.", subscription),
+            None,
+        );
+        let sequence_before = sequence(&state);
+        let interruption = {
+            let reading =
+                subscription.start(exchange.as_ref(), recorder.as_ref(), &state, sender.clone());
+            tokio::pin!(reading);
+            loop {
+                tokio::select! {
+                    result = &mut reading => break Interruption::Failed(match result {
+                        Ok(_) => "The stream closed.".to_string(),
+                        Err(err) => err.to_string(),
+                    }),
+                    command = next_command(&mut commands) => match command {
+                        ReaderCommand::Disable(reason) => break Interruption::Disabled(reason),
+                        ReaderCommand::Resubscribe => break Interruption::Resubscribe,
+                        ReaderCommand::Enable => continue,
+                    },
+                }
+            }
+        };
+        let error = match interruption {
+            Interruption::Failed(error) => error,
+            Interruption::Disabled(reason) => {
+                disable(name, reason, &state, &mut commands).await;
+                failures = 0;
+                continue;
+            }
+            Interruption::Resubscribe => {
+                info!("{} reader resubscribing.", name);
+                failures = 0;
+                continue;
+            }
+        };
+        error!("{} stream failed:  {}", name, error);
+        state.set_status(
+            name,
+            ConnectionStatus::Disconnected,
+            "The stream failed.",
+            Some(error),
+        );
+
+        // Only count the failures which did not receive any order-books.
+        if sequence(&state) > sequence_before {
+            failures = 0;
+        }
+        failures += 1;
+        let delay = if failures >= QUARANTINE_AFTER_FAILURES {
+            failures = 0;
+            warn!("Quarantining {} for {:?}.", name, QUARANTINE_DURATION);
+            state.set_status(
+                name,
+                ConnectionStatus::Quarantined,
+                format!(
+                    "The stream failed {} times in a row.  Reconnecting in {:?}.",
+                    QUARANTINE_AFTER_FAILURES, QUARANTINE_DURATION
+                ),
+                None,
+            );
+            QUARANTINE_DURATION
+        } else {
+            let delay = (RECONNECT_DELAY * 2u32.pow(failures - 1)).min(MAX_RECONNECT_DELAY);
+            state.set_status(
+                name,
+                ConnectionStatus::Reconnecting,
+                format!("Reconnecting in {:?}.", delay),
+                None,
+            );
+            delay
+        };
+        // Wait to reconnect.  Enabling or resubscribing reconnects immediately.
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            command = next_command(&mut commands) => {
+                if let ReaderCommand::Disable(reason) = command {
+                    disable(name, reason, &state, &mut commands).await;
+                }
+                failures = 0;
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::common::now_ns;
+
+/// Start a new file after an hour, or after 256MiB of uncompressed frames.
+const ROTATION: Rotation = Rotation {
+    interval: Duration::from_secs(60 * 60),
+    bytes: 256 * 1024 * 1024,
+};
+/// The number of frames buffered between the exchange readers and the writer.
+const CHANNEL_CAPACITY: usize = 10_000;
+/// Warn about dropped frames at most this often.
+const DROP_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+pub const FILE_PREFIX: &str = "frames-";
+pub const FILE_SUFFIX: &str = ".jsonl.gz";
+
+/// A websocket text frame as received from an exchange.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct RawFrame {
+    pub exchange: String,
+    /// The exchange-specific symbol of the stream.
+    pub symbol: String,
+    /// Receive time in nanoseconds since the Unix epoch.
+    pub received_ns: i64,
+    pub text: String,
+}
+
+impl RawFrame {
+    pub fn received_now(exchange: &str, symbol: &str, text: &str) -> Self {
+        Self {
+            exchange: exchange.to_string(),
+            symbol: symbol.to_string(),
+            received_ns: now_ns(),
+            text: text.to_string(),
+        }
+    }
+}
+
+/// Records raw exchange frames to rotating, gzip-compressed JSON-lines files.
+/// Cheap to clone; every clone writes to the same files.
+#[derive(Clone)]
+pub struct FrameRecorder {
+    tx: mpsc::Sender<RawFrame>,
+    dropped: Arc<Mutex<DroppedFrames>>,
+}
+
+/// The frames dropped since the latest warning about them.
+#[derive(Debug, Default)]
+struct DroppedFrames {
+    count: u64,
+    warned: Option<Instant>,
+}
+
+impl DroppedFrames {
+    /// Count a dropped frame.
+    /// Returns the number of frames dropped since the latest warning, if it is time to warn again.
+    fn count(&mut self, now: Instant) -> Option<u64> {
+        self.count += 1;
+        if self
+            .warned
+            .is_some_and(|warned| now.saturating_duration_since(warned) < DROP_WARNING_INTERVAL)
+        {
+            return None;
+        }
+        self.warned = Some(now);
+        Some(std::mem::take(&mut self.count))
+    }
+}
+
+impl FrameRecorder {
+    /// Start the writer thread.  Files are created in the given directory.
+    pub fn start(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let dir = dir.to_path_buf();
+        std::thread::Builder::new()
+            .name("frame-recorder".to_string())
+            .spawn(move || write_frames(dir, rx, ROTATION))?;
+        Ok(Self {
+            tx,
+            dropped: Default::default(),
+        })
+    }
+
+    /// Queue a frame for writing.
+    /// Frames are dropped rather than slowing down the exchange readers,
+    /// and counted in a warning every [DROP_WARNING_INTERVAL] at most.
+    pub fn record(&self, frame: RawFrame) {
+        match self.tx.try_send(frame) {
+            Ok(_) => { /* Pass */ }
+            Err(TrySendError::Full(_)) => {
+                if let Some(count) = self.dropped.lock().unwrap().count(Instant::now()) {
+                    warn!(
+                        "Frame recorder is falling behind.  Dropped {} frames since the last warning.",
+                        count
+                    );
+                }
+            }
+            Err(TrySendError::Closed(_)) => error!("Frame recorder has stopped."),
+        }
+    }
+}
+
+/// When to start a new file.
+#[derive(Clone, Copy, Debug)]
+struct Rotation {
+    interval: Duration,
+    /// The number of uncompressed bytes.
+    bytes: u64,
+}
+
+struct FrameFile {
+    encoder: GzEncoder<BufWriter<File>>,
+    opened: Instant,
+    bytes: u64,
+}
+
+impl FrameFile {
+    fn create(dir: &Path) -> io::Result<Self> {
+        let path = frame_file_path(dir);
+        info!("Recording raw frames to {}", path.display());
+        let file = File::create(path)?;
+        Ok(Self {
+            encoder: GzEncoder::new(BufWriter::new(file), Compression::default()),
+            opened: Instant::now(),
+            bytes: 0,
+        })
+    }
+
+    fn is_full(&self, rotation: &Rotation) -> bool {
+        self.bytes >= rotation.bytes || self.opened.elapsed() >= rotation.interval
+    }
+
+    fn write(&mut self, frame: &RawFrame) -> io::Result<()> {
+        let mut line = serde_json::to_vec(frame)?;
+        line.push(b'\n');
+        self.encoder.write_all(&line)?;
+        self.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.encoder.finish()?.flush()
+    }
+}
+
+fn frame_file_path(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "{}{}{}",
+        FILE_PREFIX,
+        Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+        FILE_SUFFIX
+    ))
+}
+
+/// Write frames until every recorder handle has been dropped.
+fn write_frames(dir: PathBuf, mut rx: mpsc::Receiver<RawFrame>, rotation: Rotation) {
+    let mut file: Option<FrameFile> = None;
+    while let Some(frame) = rx.blocking_recv() {
+        write_frame(&dir, &rotation, &mut file, &frame);
+        while let Ok(frame) = rx.try_recv() {
+            write_frame(&dir, &rotation, &mut file, &frame);
+        }
+        // Flush once the queue is drained, so that a file is readable
+        // up to the latest frame even if the process is killed.
+        if let Some(Err(err)) = file.as_mut().map(|f| f.encoder.flush()) {
+            error!("Failed to flush the frame recording:  {}", err);
+        }
+    }
+    if let Some(Err(err)) = file.map(FrameFile::finish) {
+        error!("Failed to finish the frame recording:  {}", err);
+    }
+}
+
+fn write_frame(dir: &Path, rotation: &Rotation, file: &mut Option<FrameFile>, frame: &RawFrame) {
+    if let Err(err) = try_write_frame(dir, rotation, file, frame) {
+        error!("Failed to record a {} frame:  {}", frame.exchange, err);
+        // Start a new file for the next frame.
+        *file = None;
+    }
+}
+
+fn try_write_frame(
+    dir: &Path,
+    rotation: &Rotation,
+    file: &mut Option<FrameFile>,
+    frame: &RawFrame,
+) -> io::Result<()> {
+    if file.as_ref().is_some_and(|f| f.is_full(rotation)) {
+        if let Some(full) = file.take() {
+            full.finish()?;
+        }
+    }
+    if file.is_none() {
+        *file = Some(FrameFile::create(dir)?);
+    }
+    file.as_mut().unwrap().write(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::time::{Duration, Instant};
+
+    use flate2::read::GzDecoder;
+    use tokio::sync::mpsc;
+
+    use super::{
+        write_frames, DroppedFrames, FrameRecorder, RawFrame, Rotation, DROP_WARNING_INTERVAL,
+        FILE_PREFIX, FILE_SUFFIX,
+    };
+
+    fn frame(received_ns: i64) -> RawFrame {
+        RawFrame {
+            exchange: "binance".to_string(),
+            symbol: "ethbtc".to_string(),
+            received_ns,
+            text: "{}".to_string(),
+        }
+    }
+
+    #[test]
+    fn record_and_rotate_frames() {
+        let dir = std::env::temp_dir().join(format!("recorder-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (tx, rx) = mpsc::channel(10);
+        // Every frame fills a file.
+        let rotation = Rotation {
+            interval: Duration::from_secs(60),
+            bytes: 1,
+        };
+        let writer_dir = dir.clone();
+        let writer = std::thread::spawn(move || write_frames(writer_dir, rx, rotation));
+        for received_ns in 1..=3 {
+            tx.blocking_send(frame(received_ns)).unwrap();
+            // File names are unique to the millisecond.
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        drop(tx);
+        writer.join().unwrap();
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        let frames: Vec<Vec<RawFrame>> = files
+            .iter()
+            .map(|path| {
+                let name = path.file_name().unwrap().to_str().unwrap();
+                assert!(name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX));
+                BufReader::new(GzDecoder::new(std::fs::File::open(path).unwrap()))
+                    .lines()
+                    .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+                    .collect()
+            })
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(vec![vec![frame(1)], vec![frame(2)], vec![frame(3)]], frames);
+    }
+
+    #[test]
+    fn drop_frames_when_behind() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let recorder = FrameRecorder {
+            tx,
+            dropped: Default::default(),
+        };
+        recorder.record(frame(1));
+        recorder.record(frame(2));
+        assert_eq!(frame(1), rx.try_recv().unwrap());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(0, recorder.dropped.lock().unwrap().count);
+    }
+
+    #[test]
+    fn warn_about_dropped_frames_at_intervals() {
+        let t0 = Instant::now();
+        let mut dropped = DroppedFrames::default();
+        assert_eq!(Some(1), dropped.count(t0));
+        assert_eq!(None, dropped.count(t0 + Duration::from_secs(1)));
+        assert_eq!(None, dropped.count(t0 + Duration::from_secs(2)));
+        // The frames dropped since the latest warning are counted in the next one.
+        assert_eq!(Some(3), dropped.count(t0 + DROP_WARNING_INTERVAL));
+        assert_eq!(None, dropped.count(t0 + DROP_WARNING_INTERVAL));
+    }
+}
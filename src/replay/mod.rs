@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use log::{debug, error, info};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::common::OrderBook;
+use crate::exchange::metadata::InstrumentInfo;
+use crate::exchange::{BookBuilder, Exchange};
+use crate::reader::Subscription;
+use crate::recorder::{RawFrame, FILE_PREFIX, FILE_SUFFIX};
+use crate::synthetic::SyntheticBook;
+
+/// How fast recorded frames are replayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// A multiple of the original speed, eg. `2.0` replays twice as fast.
+    Multiple(f64),
+    /// Without waiting between frames.
+    AsFastAsPossible,
+}
+
+/// The recording files to replay, in order.
+/// Directories are expanded to the frame files they contain.
+pub fn frame_files(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut dir_files: Vec<PathBuf> = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| is_frame_file(p))
+                .collect();
+            // File names start with the time they were created.
+            dir_files.sort();
+            files.append(&mut dir_files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn is_frame_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX))
+        .unwrap_or(false)
+}
+
+/// Read the frames of a recording file.
+/// Reading stops at the first error, eg. the end of a file which was
+/// not finished because the recording process was killed.
+fn read_frames(path: &Path, sink: &mpsc::Sender<RawFrame>) -> io::Result<()> {
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    for line in reader.lines() {
+        let frame = serde_json::from_str::<RawFrame>(&line?)?;
+        if sink.blocking_send(frame).is_err() {
+            // The replay has stopped.
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Replay the recorded frames of the given exchanges through their adapters.
+/// `exchanges` pairs each exchange with the streams to replay.
+/// Returns a stream of order-books, as the live exchange readers do.
+pub fn start(
+    files: Vec<PathBuf>,
+    exchanges: Vec<(Arc<dyn Exchange>, Subscription)>,
+    speed: ReplaySpeed,
+) -> mpsc::Receiver<OrderBook> {
+    let (frames_tx, frames_rx) = mpsc::channel(1000);
+    let (tx, rx) = mpsc::channel(100);
+
+    std::thread::spawn(move || {
+        for file in files {
+            info!("Replaying {}", file.display());
+            if let Err(err) = read_frames(&file, &frames_tx) {
+                error!("Error reading {}:  {}", file.display(), err);
+            }
+        }
+    });
+    tokio::spawn(async move {
+        replay_frames(frames_rx, exchanges, speed, tx).await;
+        info!("Replay finished.");
+    });
+    rx
+}
+
+/// `<exchange-symbol> => (<book-builder>, <leg-index>)`
+type SymbolBookBuilders<'a> = HashMap<&'a str, (BookBuilder<'a, dyn Exchange>, Option<usize>)>;
+
+async fn replay_frames(
+    mut frames: mpsc::Receiver<RawFrame>,
+    exchanges: Vec<(Arc<dyn Exchange>, Subscription)>,
+    speed: ReplaySpeed,
+    sink: mpsc::Sender<OrderBook>,
+) {
+    // Each stream's instrument, and the index of its leg if it is a synthetic instrument's.
+    let instruments: Vec<(&dyn Exchange, &InstrumentInfo, Option<usize>)> = exchanges
+        .iter()
+        .flat_map(|(exchange, subscription)| {
+            let exchange = exchange.as_ref();
+            match subscription {
+                Subscription::Symbol(instrument) => vec![(exchange, instrument, None)],
+                Subscription::Synthetic(_, legs) => legs
+                    .iter()
+                    .enumerate()
+                    .map(|(index, leg)| (exchange, leg, Some(index)))
+                    .collect(),
+            }
+        })
+        .collect();
+    // `<exchange-name> => <exchange-symbol> => (<book-builder>, <leg-index>)`
+    let mut book_builders: HashMap<&str, SymbolBookBuilders> = HashMap::new();
+    for &(exchange, instrument, leg) in &instruments {
+        book_builders.entry(exchange.name()).or_default().insert(
+            instrument.symbol.as_str(),
+            (BookBuilder::new(exchange, instrument), leg),
+        );
+    }
+    // `<exchange-name> => <synthetic-book>`
+    let mut synthetic_books: HashMap<&str, SyntheticBook> = exchanges
+        .iter()
+        .filter_map(|(exchange, subscription)| match subscription {
+            Subscription::Symbol(_) => None,
+            Subscription::Synthetic(instrument, _) => {
+                Some((exchange.name(), SyntheticBook::new(instrument.clone())))
+            }
+        })
+        .collect();
+
+    // The receive time of the first frame, and when it was replayed.
+    let mut start: Option<(i64, Instant)> = None;
+    while let Some(frame) = frames.recv().await {
+        let (book_builder, leg) = match book_builders
+            .get_mut(frame.exchange.as_str())
+            .and_then(|symbols| symbols.get_mut(frame.symbol.as_str()))
+        {
+            Some((book_builder, leg)) => (book_builder, *leg),
+            None => continue,
+        };
+
+        if let ReplaySpeed::Multiple(multiple) = speed {
+            let (first_ns, replay_start) =
+                *start.get_or_insert((frame.received_ns, Instant::now()));
+            let offset_ns = (frame.received_ns - first_ns).max(0) as f64 / multiple;
+            tokio::time::sleep_until(replay_start + Duration::from_nanos(offset_ns as u64)).await;
+        }
+
+        debug!("Replaying {} frame:  {}", frame.exchange, frame.text);
+        match book_builder.build(&frame) {
+            // Frames which are not order-books, eg. subscription confirmations, are skipped.
+            Err(err) => debug!("Skipping {} frame:  {}", frame.exchange, err),
+            Ok(order_book) => {
+                // A synthetic order-book is sent once both of its legs have an order-book.
+                let order_book = match leg {
+                    None => order_book,
+                    Some(index) => match synthetic_books
+                        .get_mut(frame.exchange.as_str())
+                        .and_then(|synthetic_book| synthetic_book.update(index, order_book))
+                    {
+                        Some(order_book) => order_book,
+                        None => continue,
+                    },
+                };
+                if sink.send(order_book).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::{frame_files, start, ReplaySpeed};
+    use crate::common::instrument::Instrument;
+    use crate::common::OrderBook;
+    use crate::exchange::binance::Binance;
+    use crate::exchange::metadata::InstrumentInfo;
+    use crate::reader::Subscription;
+    use crate::recorder::RawFrame;
+    use crate::synthetic::SyntheticInstrument;
+
+    /// Record binance frames of the given symbols and receive times, and replay them.
+    async fn replay(
+        name: &str,
+        frames: &[(&str, i64)],
+        subscription: Subscription,
+    ) -> Vec<OrderBook> {
+        let dir = std::env::temp_dir().join(format!("replay-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(dir.join("frames-20220108T131433.000.jsonl.gz")).unwrap(),
+            Compression::default(),
+        );
+        let text = include_str!("../../tests/binance_order_book_message.json");
+        for &(symbol, received_ns) in frames {
+            let frame = RawFrame {
+                exchange: "binance".to_string(),
+                symbol: symbol.to_string(),
+                received_ns,
+                text: text.to_string(),
+            };
+            writeln!(encoder, "{}", serde_json::to_string(&frame).unwrap()).unwrap();
+        }
+        encoder.finish().unwrap();
+
+        let files = frame_files(std::slice::from_ref(&dir)).unwrap();
+        assert_eq!(1, files.len());
+        let mut rx = start(
+            files,
+            vec![(Arc::new(Binance), subscription)],
+            ReplaySpeed::AsFastAsPossible,
+        );
+        let mut order_books = vec![];
+        while let Some(order_book) = rx.recv().await {
+            order_books.push(order_book);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        order_books
+    }
+
+    #[tokio::test]
+    async fn replay_recorded_frames() {
+        let order_books = replay(
+            "symbol",
+            &[("ethbtc", 1), ("xrpbtc", 2), ("ethbtc", 3)],
+            Subscription::Symbol(InstrumentInfo::unchecked("ethbtc")),
+        )
+        .await;
+
+        // The frames for the other symbol are not replayed.
+        assert_eq!(2, order_books.len());
+        assert_eq!(0.076424, order_books[0].bids[0].price);
+        assert_eq!(1, order_books[0].received_ns);
+    }
+
+    #[tokio::test]
+    async fn replay_synthetic_legs() {
+        let instrument = SyntheticInstrument::new(
+            Instrument::spot("ETH", "BTC"),
+            [
+                Instrument::spot("ETH", "USDT"),
+                Instrument::spot("BTC", "USDT"),
+            ],
+        )
+        .unwrap();
+        let order_books = replay(
+            "synthetic",
+            &[("ethusdt", 1), ("btcusdt", 2), ("ethusdt", 3)],
+            Subscription::Synthetic(
+                Arc::new(instrument),
+                [
+                    InstrumentInfo::unchecked("ethusdt"),
+                    InstrumentInfo::unchecked("btcusdt"),
+                ],
+            ),
+        )
+        .await;
+
+        // The synthetic order-book is sent once both legs have an order-book, and on every update.
+        assert_eq!(2, order_books.len());
+        assert_eq!(
+            vec![2, 3],
+            order_books
+                .iter()
+                .map(|order_book| order_book.received_ns)
+                .collect::<Vec<_>>()
+        );
+        // The second leg is quoted the other way round, so its asks are the synthetic bids'.
+        assert!((order_books[0].bids[0].price - 0.076424 / 0.076425).abs() < 1e-12);
+        assert!((order_books[0].asks[0].price - 0.076425 / 0.076424).abs() < 1e-12);
+    }
+}
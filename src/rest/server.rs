@@ -0,0 +1,312 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{Extension, Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::common::instrument::Instrument;
+use crate::proto;
+use crate::state::MarketState;
+
+/// Serves the merged order-book and the exchanges' states over HTTP.
+pub struct RestServer {
+    symbol: Instrument,
+    state: Arc<MarketState>,
+    /// The number of live exchanges required to be ready.
+    min_live_exchanges: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct BookQuery {
+    depth: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct BookResponse {
+    symbol: String,
+    #[serde(flatten)]
+    summary: proto::Summary,
+}
+
+#[derive(Debug, Serialize)]
+struct ExchangeResponse {
+    exchange: &'static str,
+    status: &'static str,
+    /// Milliseconds since the exchange's latest order-book was merged.
+    book_age_ms: Option<u128>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    live_exchanges: usize,
+    min_live_exchanges: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+fn error_response(status: StatusCode, error: String) -> Response {
+    (status, Json(ErrorResponse { error })).into_response()
+}
+
+impl RestServer {
+    pub fn new(symbol: Instrument, state: Arc<MarketState>, min_live_exchanges: usize) -> Self {
+        Self {
+            symbol,
+            state,
+            min_live_exchanges,
+        }
+    }
+
+    pub async fn serve(self, addr: SocketAddr) -> Result<(), hyper::Error> {
+        axum::Server::bind(&addr)
+            .serve(self.router().into_make_service())
+            .await
+    }
+
+    fn router(self) -> Router {
+        Router::new()
+            .route("/book/:symbol", get(book))
+            .route("/exchanges", get(exchanges))
+            .route("/health", get(health))
+            .route("/metrics", get(metrics))
+            .layer(Extension(Arc::new(self)))
+    }
+}
+
+/// `GET /book/{symbol}?depth=N`
+/// The latest merged order-book.  The symbol may be given as eg. `ethbtc` or `ETH-BTC`.
+async fn book(
+    Path(symbol): Path<String>,
+    Query(query): Query<BookQuery>,
+    Extension(server): Extension<Arc<RestServer>>,
+) -> Response {
+    if symbol.parse::<Instrument>().as_ref() != Ok(&server.symbol) {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            format!("Unknown symbol '{}'.", symbol),
+        );
+    }
+    match server.state.latest() {
+        None => error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No order-books have been merged yet.".to_string(),
+        ),
+        Some(merged_book) => {
+            let summary = merged_book.summary;
+            let summary = match query.depth {
+                Some(depth) => summary.with_depth(depth),
+                None => summary,
+            };
+            Json(BookResponse {
+                symbol: server.symbol.to_string(),
+                summary,
+            })
+            .into_response()
+        }
+    }
+}
+
+/// `GET /exchanges`
+/// Each exchange's connection state and order-book age.
+async fn exchanges(Extension(server): Extension<Arc<RestServer>>) -> Response {
+    let exchanges: Vec<ExchangeResponse> = server
+        .state
+        .exchanges()
+        .into_iter()
+        .map(|(exchange, state)| ExchangeResponse {
+            exchange,
+            status: state.status.name(),
+            book_age_ms: state.book_age().map(|age| age.as_millis()),
+            last_error: state.last_error,
+        })
+        .collect();
+    Json(exchanges).into_response()
+}
+
+/// `GET /health`
+/// Ready when enough exchanges are live.
+async fn health(Extension(server): Extension<Arc<RestServer>>) -> Response {
+    let live_exchanges = server.state.live_exchanges();
+    let ready = live_exchanges >= server.min_live_exchanges;
+    let response = HealthResponse {
+        status: if ready { "ready" } else { "unavailable" },
+        live_exchanges,
+        min_live_exchanges: server.min_live_exchanges,
+    };
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(response)).into_response()
+}
+
+/// `GET /metrics`
+/// Metrics in the Prometheus text format.
+async fn metrics(Extension(server): Extension<Arc<RestServer>>) -> String {
+    let mut metrics = String::new();
+    metrics.push_str("# TYPE orderbook_live_exchanges gauge\n");
+    metrics.push_str(&format!(
+        "orderbook_live_exchanges {}\n",
+        server.state.live_exchanges()
+    ));
+
+    let clients = server.state.clients();
+    metrics.push_str("# TYPE orderbook_client_streams_active gauge\n");
+    for (client, streams) in &clients {
+        metrics.push_str(&format!(
+            "orderbook_client_streams_active{{client=\"{}\"}} {}\n",
+            escape_label(client),
+            streams.active
+        ));
+    }
+    metrics.push_str("# TYPE orderbook_client_streams_total counter\n");
+    for (client, streams) in &clients {
+        metrics.push_str(&format!(
+            "orderbook_client_streams_total{{client=\"{}\"}} {}\n",
+            escape_label(client),
+            streams.total
+        ));
+    }
+    metrics
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::RestServer;
+    use crate::common::fixtures::level;
+    use crate::merger::MergedBook;
+    use crate::proto;
+    use crate::state::MarketState;
+
+    fn server(state: &Arc<MarketState>, min_live_exchanges: usize) -> RestServer {
+        RestServer::new(
+            "ETH/BTC".parse().unwrap(),
+            state.clone(),
+            min_live_exchanges,
+        )
+    }
+
+    async fn get(server: RestServer, uri: &str) -> (StatusCode, Vec<u8>) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn get_json(server: RestServer, uri: &str) -> (StatusCode, Value) {
+        let (status, body) = get(server, uri).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn get_book() {
+        let state = Arc::new(MarketState::default());
+        let (status, _) = get_json(server(&state, 1), "/book/ethbtc").await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+
+        state.set_latest(MergedBook {
+            sequence: 1,
+            timestamp_ns: 0,
+            summary: proto::Summary {
+                spread: 1.0,
+                bids: vec![level("a", 10.0, 1.0), level("b", 9.0, 2.0)],
+                asks: vec![level("a", 11.0, 1.0), level("b", 12.0, 2.0)],
+            },
+            order_books: Arc::new(HashMap::new()),
+        });
+        let (status, body) = get_json(server(&state, 1), "/book/ETH-BTC?depth=1").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!("ETH/BTC"), body["symbol"]);
+        assert_eq!(
+            json!([{"exchange": "a", "price": 10.0, "amount": 1.0}]),
+            body["bids"]
+        );
+        assert_eq!(
+            json!([{"exchange": "a", "price": 11.0, "amount": 1.0}]),
+            body["asks"]
+        );
+        let (_, body) = get_json(server(&state, 1), "/book/ethbtc").await;
+        assert_eq!(2, body["bids"].as_array().unwrap().len());
+
+        let (status, _) = get_json(server(&state, 1), "/book/btcusd").await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+    }
+
+    #[tokio::test]
+    async fn get_exchanges() {
+        let state = Arc::new(MarketState::default());
+        state.book_updated("binance");
+        let (status, body) = get_json(server(&state, 1), "/exchanges").await;
+        assert_eq!(StatusCode::OK, status);
+        let exchanges = body.as_array().unwrap();
+        assert_eq!(1, exchanges.len());
+        assert_eq!(json!("binance"), exchanges[0]["exchange"]);
+        assert_eq!(json!("live"), exchanges[0]["status"]);
+        assert!(exchanges[0]["book_age_ms"].is_u64());
+        assert!(exchanges[0]["last_error"].is_null());
+    }
+
+    #[tokio::test]
+    async fn get_health() {
+        let state = Arc::new(MarketState::default());
+        state.book_updated("binance");
+        let (status, body) = get_json(server(&state, 1), "/health").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            json!({"status": "ready", "live_exchanges": 1, "min_live_exchanges": 1}),
+            body
+        );
+
+        let (status, body) = get_json(server(&state, 2), "/health").await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!(json!("unavailable"), body["status"]);
+    }
+
+    #[tokio::test]
+    async fn get_metrics() {
+        let state = Arc::new(MarketState::default());
+        state.book_updated("binance");
+        state.client_connected("desk-a (trader-1)", None);
+        state.client_connected("desk-\"b\"", None);
+        state.client_disconnected("desk-\"b\"");
+        let (status, body) = get(server(&state, 1), "/metrics").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            "# TYPE orderbook_live_exchanges gauge\n\
+             orderbook_live_exchanges 1\n\
+             # TYPE orderbook_client_streams_active gauge\n\
+             orderbook_client_streams_active{client=\"desk-\\\"b\\\"\"} 0\n\
+             orderbook_client_streams_active{client=\"desk-a (trader-1)\"} 1\n\
+             # TYPE orderbook_client_streams_total counter\n\
+             orderbook_client_streams_total{client=\"desk-\\\"b\\\"\"} 1\n\
+             orderbook_client_streams_total{client=\"desk-a (trader-1)\"} 1\n",
+            String::from_utf8(body).unwrap()
+        );
+    }
+}
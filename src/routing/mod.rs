@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+use crate::proto;
+
+/// An exchange's trading costs and constraints, from the configuration file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExchangeTerms {
+    /// The fee of taking liquidity, in basis points of the traded amount.
+    pub taker_fee_bps: f64,
+    /// The minimum size of an order, in the base currency.
+    pub min_order_size: f64,
+    /// Whether the symbol is traded on both legs of a synthetic instrument,
+    /// so that the taker fee is paid on each leg.
+    pub synthetic: bool,
+}
+
+impl ExchangeTerms {
+    /// The price of taking a level including the taker fee:  higher to buy and lower to sell.
+    pub fn fee_adjusted_price(&self, side: proto::Side, price: f64) -> f64 {
+        let fee = self.taker_fee_bps / 10_000.0;
+        let legs = if self.synthetic { 2 } else { 1 };
+        match side {
+            proto::Side::Buy => price * (1.0 + fee).powi(legs),
+            proto::Side::Sell => price * (1.0 - fee).powi(legs),
+        }
+    }
+
+    /// The taker fee of taking an amount of a level, in the quote currency.
+    pub fn fee(&self, side: proto::Side, price: f64, amount: f64) -> f64 {
+        (self.fee_adjusted_price(side, price) - price).abs() * amount
+    }
+}
+
+/// An order to split into child orders across the exchanges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParentOrder {
+    pub side: proto::Side,
+    /// In the base currency.
+    pub quantity: f64,
+    /// The worst price to fill at.
+    pub limit_price: Option<f64>,
+}
+
+fn terms_of(terms: &HashMap<String, ExchangeTerms>, exchange: &str) -> ExchangeTerms {
+    terms.get(exchange).copied().unwrap_or_default()
+}
+
+/// Plan the child orders of the parent order against the merged order-book.
+/// Levels are taken by their fee-adjusted price, so a cheaper exchange may lose to one with lower fees.
+/// An exchange which would be routed less than its minimum order size is skipped,
+/// and its quantity is routed to the other exchanges.
+pub fn route(
+    summary: &proto::Summary,
+    order: &ParentOrder,
+    terms: &HashMap<String, ExchangeTerms>,
+) -> proto::RoutingPlan {
+    let mut skipped: Vec<proto::SkippedExchange> = vec![];
+    loop {
+        let child_orders = allocate(summary, order, terms, &skipped);
+        // Skip the exchange with the worst price first, since skipping it adds to the others.
+        let too_small = child_orders
+            .iter()
+            .rev()
+            .find(|child| child.quantity < terms_of(terms, &child.exchange).min_order_size);
+        match too_small {
+            Some(child) => skipped.push(proto::SkippedExchange {
+                exchange: child.exchange.clone(),
+                quantity: child.quantity,
+                min_order_size: terms_of(terms, &child.exchange).min_order_size,
+            }),
+            None => return plan(order, child_orders, skipped),
+        }
+    }
+}
+
+/// Fill the parent order level by level, best fee-adjusted price first,
+/// with one child order of each exchange.
+fn allocate(
+    summary: &proto::Summary,
+    order: &ParentOrder,
+    terms: &HashMap<String, ExchangeTerms>,
+    skipped: &[proto::SkippedExchange],
+) -> Vec<proto::ChildOrder> {
+    let within_limit = |price: f64| match (order.side, order.limit_price) {
+        (_, None) => true,
+        (proto::Side::Buy, Some(limit_price)) => price <= limit_price,
+        (proto::Side::Sell, Some(limit_price)) => price >= limit_price,
+    };
+    let levels = match order.side {
+        proto::Side::Buy => &summary.asks,
+        proto::Side::Sell => &summary.bids,
+    };
+    let mut levels: Vec<_> = levels
+        .iter()
+        .filter(|level| within_limit(level.price))
+        .filter(|level| !skipped.iter().any(|skip| skip.exchange == level.exchange))
+        .map(|level| {
+            let terms = terms_of(terms, &level.exchange);
+            (level, terms.fee_adjusted_price(order.side, level.price))
+        })
+        .collect();
+    levels.sort_by(|(_, a), (_, b)| match order.side {
+        proto::Side::Buy => a.partial_cmp(b).unwrap(),
+        proto::Side::Sell => b.partial_cmp(a).unwrap(),
+    });
+
+    let mut remaining = order.quantity;
+    let mut child_orders: Vec<proto::ChildOrder> = vec![];
+    for (level, _) in levels {
+        if remaining <= 0.0 {
+            break;
+        }
+        let quantity = level.amount.min(remaining);
+        remaining -= quantity;
+
+        let child = match child_orders
+            .iter_mut()
+            .find(|child| child.exchange == level.exchange)
+        {
+            Some(child) => child,
+            None => {
+                child_orders.push(proto::ChildOrder {
+                    exchange: level.exchange.clone(),
+                    side: order.side as i32,
+                    ..Default::default()
+                });
+                child_orders.last_mut().unwrap()
+            }
+        };
+        child.average_price = (child.average_price * child.quantity + level.price * quantity)
+            / (child.quantity + quantity);
+        child.quantity += quantity;
+        // An exchange's levels have the same fee, so its last level has its worst price.
+        child.limit_price = level.price;
+        child.fee += terms_of(terms, &level.exchange).fee(order.side, level.price, quantity);
+    }
+    child_orders
+}
+
+fn plan(
+    order: &ParentOrder,
+    child_orders: Vec<proto::ChildOrder>,
+    skipped: Vec<proto::SkippedExchange>,
+) -> proto::RoutingPlan {
+    let base_quantity: f64 = child_orders.iter().map(|child| child.quantity).sum();
+    let quote_quantity: f64 = child_orders
+        .iter()
+        .map(|child| child.average_price * child.quantity)
+        .sum();
+    let fees: f64 = child_orders.iter().map(|child| child.fee).sum();
+    let (average_price, net_average_price) = match (order.side, base_quantity) {
+        (_, 0.0) => (0.0, 0.0),
+        (proto::Side::Buy, _) => (
+            quote_quantity / base_quantity,
+            (quote_quantity + fees) / base_quantity,
+        ),
+        (proto::Side::Sell, _) => (
+            quote_quantity / base_quantity,
+            (quote_quantity - fees) / base_quantity,
+        ),
+    };
+    proto::RoutingPlan {
+        child_orders,
+        base_quantity,
+        quote_quantity,
+        fees,
+        average_price,
+        net_average_price,
+        unrouted_quantity: (order.quantity - base_quantity).max(0.0),
+        skipped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{route, ExchangeTerms, ParentOrder};
+    use crate::common::fixtures::level;
+    use crate::proto;
+
+    fn summary() -> proto::Summary {
+        proto::Summary {
+            spread: 0.5,
+            bids: vec![level("a", 99.5, 1.0), level("b", 99.4, 1.0)],
+            asks: vec![
+                level("a", 100.0, 1.0),
+                level("b", 100.2, 1.0),
+                level("b", 101.0, 2.0),
+            ],
+        }
+    }
+
+    fn terms(a_min_order_size: f64) -> HashMap<String, ExchangeTerms> {
+        HashMap::from([
+            (
+                "a".to_string(),
+                ExchangeTerms {
+                    taker_fee_bps: 50.0,
+                    min_order_size: a_min_order_size,
+                    synthetic: false,
+                },
+            ),
+            (
+                "b".to_string(),
+                ExchangeTerms {
+                    taker_fee_bps: 10.0,
+                    min_order_size: 0.0,
+                    synthetic: false,
+                },
+            ),
+        ])
+    }
+
+    fn buy(quantity: f64, limit_price: Option<f64>) -> ParentOrder {
+        ParentOrder {
+            side: proto::Side::Buy,
+            quantity,
+            limit_price,
+        }
+    }
+
+    #[test]
+    fn route_by_fee_adjusted_price() {
+        let plan = route(&summary(), &buy(1.5, None), &terms(0.0));
+        let children: Vec<_> = plan
+            .child_orders
+            .iter()
+            .map(|child| (child.exchange.as_str(), child.quantity, child.limit_price))
+            .collect();
+        // The lower fee of b outweighs its higher price.
+        assert_eq!(vec![("b", 1.0, 100.2), ("a", 0.5, 100.0)], children);
+        assert_eq!(1.5, plan.base_quantity);
+        assert!((plan.fees - (0.1002 + 0.25)).abs() < 1e-9);
+        assert!(plan.net_average_price > plan.average_price);
+        assert_eq!(0.0, plan.unrouted_quantity);
+
+        let sell = ParentOrder {
+            side: proto::Side::Sell,
+            quantity: 1.0,
+            limit_price: None,
+        };
+        let plan = route(&summary(), &sell, &terms(0.0));
+        assert_eq!("b", plan.child_orders[0].exchange);
+        assert!(plan.net_average_price < plan.average_price);
+    }
+
+    #[test]
+    fn route_above_min_order_sizes() {
+        let plan = route(&summary(), &buy(1.5, None), &terms(1.0));
+        assert_eq!(1, plan.child_orders.len());
+        let child = &plan.child_orders[0];
+        assert_eq!(
+            ("b", 1.5, 101.0),
+            (child.exchange.as_str(), child.quantity, child.limit_price)
+        );
+        assert!((child.average_price - (100.2 + 50.5) / 1.5).abs() < 1e-9);
+        assert_eq!(
+            vec![proto::SkippedExchange {
+                exchange: "a".to_string(),
+                quantity: 0.5,
+                min_order_size: 1.0,
+            }],
+            plan.skipped
+        );
+
+        // The limit price leaves b too shallow for the rest.
+        let plan = route(&summary(), &buy(1.5, Some(100.5)), &terms(1.0));
+        assert_eq!(1.0, plan.base_quantity);
+        assert_eq!(0.5, plan.unrouted_quantity);
+    }
+
+    #[test]
+    fn pay_the_fee_on_each_synthetic_leg() {
+        let terms = ExchangeTerms {
+            taker_fee_bps: 100.0,
+            min_order_size: 0.0,
+            synthetic: true,
+        };
+        let buy = terms.fee_adjusted_price(proto::Side::Buy, 100.0);
+        let sell = terms.fee_adjusted_price(proto::Side::Sell, 100.0);
+        assert!((buy - 102.01).abs() < 1e-9);
+        assert!((sell - 98.01).abs() < 1e-9);
+        assert!((terms.fee(proto::Side::Sell, 100.0, 2.0) - 3.98).abs() < 1e-9);
+    }
+}
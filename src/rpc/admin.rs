@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::{info, warn};
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+
+use proto::orderbook_admin_server::OrderbookAdmin;
+
+use crate::common::instrument::Instrument;
+use crate::merger::MergerCommand;
+use crate::proto;
+use crate::reader::{ReaderCommand, ReaderCommands};
+use crate::rpc::auth::authenticated_client;
+use crate::state::{ConnectionStatus, MarketState};
+
+pub struct OrderbookAdminService {
+    /// The symbol being merged.
+    symbol: Instrument,
+    /// The exchanges being merged.
+    exchanges: Vec<&'static str>,
+    /// The exchange readers' command channels.  Empty when replaying.
+    readers: ReaderCommands,
+    merger_tx: mpsc::Sender<MergerCommand>,
+    state: Arc<MarketState>,
+}
+
+/// A validated command.
+struct Command {
+    /// The admin's name.
+    admin: String,
+    exchange: &'static str,
+    reason: String,
+}
+
+impl OrderbookAdminService {
+    pub fn new(
+        symbol: Instrument,
+        exchanges: Vec<&'static str>,
+        readers: ReaderCommands,
+        merger_tx: mpsc::Sender<MergerCommand>,
+        state: Arc<MarketState>,
+    ) -> Self {
+        Self {
+            symbol,
+            exchanges,
+            readers,
+            merger_tx,
+            state,
+        }
+    }
+
+    /// Check that the client is an admin, and that the command is for
+    /// a served symbol and exchange.
+    #[allow(clippy::result_large_err)]
+    fn validate(&self, request: Request<proto::ExchangeCommand>) -> Result<Command, Status> {
+        let client = authenticated_client(&request)
+            .ok_or_else(|| Status::unauthenticated("A bearer token is required."))?;
+        if !client.entitlements.admin {
+            warn!("Client '{}' is not an admin.", client.name);
+            return Err(Status::permission_denied("Not entitled to admin commands."));
+        }
+        let admin = client.name.clone();
+        let entitlements = client.entitlements.clone();
+        let command = request.into_inner();
+
+        let symbol = match command.symbol.as_str() {
+            "" => self.symbol.clone(),
+            symbol => symbol
+                .parse::<Instrument>()
+                .map_err(Status::invalid_argument)?,
+        };
+        entitlements.check_symbol(&symbol)?;
+        if symbol != self.symbol {
+            return Err(Status::not_found(format!("{} is not served.", symbol)));
+        }
+        let exchange = self
+            .exchanges
+            .iter()
+            .copied()
+            .find(|exchange| *exchange == command.exchange)
+            .ok_or_else(|| {
+                Status::not_found(format!("Unknown exchange '{}'.", command.exchange))
+            })?;
+        entitlements.check_exchanges(&[command.exchange])?;
+
+        let reason = match command.reason.as_str() {
+            "" => format!("By '{}'.", admin),
+            reason => format!("By '{}':  {}", admin, reason),
+        };
+        Ok(Command {
+            admin,
+            exchange,
+            reason,
+        })
+    }
+
+    async fn send_to_merger(&self, command: MergerCommand) -> Result<(), Status> {
+        self.merger_tx
+            .send(command)
+            .await
+            .map_err(|_| Status::unavailable("The merger has stopped."))
+    }
+
+    /// Send the command to the exchange's reader, if it is read from a live stream.
+    /// Returns whether there is a reader.
+    async fn send_to_reader(
+        &self,
+        exchange: &'static str,
+        command: ReaderCommand,
+    ) -> Result<bool, Status> {
+        match self.readers.get(exchange) {
+            None => Ok(false),
+            Some(reader) => {
+                reader.send(command).await.map(|_| true).map_err(|_| {
+                    Status::unavailable(format!("The {} reader has stopped.", exchange))
+                })
+            }
+        }
+    }
+}
+
+fn reply(message: String) -> Response<proto::CommandReply> {
+    Response::new(proto::CommandReply { message })
+}
+
+#[async_trait]
+impl OrderbookAdmin for OrderbookAdminService {
+    async fn disable_exchange(
+        &self,
+        request: Request<proto::ExchangeCommand>,
+    ) -> Result<Response<proto::CommandReply>, Status> {
+        let command = self.validate(request)?;
+        info!("'{}' disabled {}.", command.admin, command.exchange);
+        self.send_to_merger(MergerCommand::Disable(command.exchange))
+            .await?;
+        let reason = format!("Disabled.  {}", command.reason);
+        if !self
+            .send_to_reader(command.exchange, ReaderCommand::Disable(reason.clone()))
+            .await?
+        {
+            self.state
+                .set_status(command.exchange, ConnectionStatus::Disabled, reason, None);
+        }
+        Ok(reply(format!("Disabled {}.", command.exchange)))
+    }
+
+    async fn enable_exchange(
+        &self,
+        request: Request<proto::ExchangeCommand>,
+    ) -> Result<Response<proto::CommandReply>, Status> {
+        let command = self.validate(request)?;
+        info!("'{}' enabled {}.", command.admin, command.exchange);
+        self.send_to_merger(MergerCommand::Enable(command.exchange))
+            .await?;
+        if !self
+            .send_to_reader(command.exchange, ReaderCommand::Enable)
+            .await?
+        {
+            // Replayed order-books keep arriving, so it is subscribed already.
+            self.state.set_status(
+                command.exchange,
+                ConnectionStatus::Subscribed,
+                format!("Enabled.  {}", command.reason),
+                None,
+            );
+        }
+        Ok(reply(format!("Enabled {}.", command.exchange)))
+    }
+
+    async fn resubscribe(
+        &self,
+        request: Request<proto::ExchangeCommand>,
+    ) -> Result<Response<proto::CommandReply>, Status> {
+        let command = self.validate(request)?;
+        info!("'{}' resubscribed {}.", command.admin, command.exchange);
+        if !self
+            .send_to_reader(command.exchange, ReaderCommand::Resubscribe)
+            .await?
+        {
+            return Err(Status::failed_precondition(format!(
+                "{} is not read from a live stream.",
+                command.exchange
+            )));
+        }
+        Ok(reply(format!("Resubscribing to {}.", command.exchange)))
+    }
+
+    async fn clear_book(
+        &self,
+        request: Request<proto::ExchangeCommand>,
+    ) -> Result<Response<proto::CommandReply>, Status> {
+        let command = self.validate(request)?;
+        info!(
+            "'{}' cleared the {} order-book.",
+            command.admin, command.exchange
+        );
+        self.send_to_merger(MergerCommand::ClearBook(command.exchange))
+            .await?;
+        Ok(reply(format!(
+            "Cleared the {} order-book.",
+            command.exchange
+        )))
+    }
+}
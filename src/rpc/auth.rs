@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Arc;
+
+use log::warn;
+use serde::Deserialize;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::common::instrument::Instrument;
+
+const AUTHORIZATION: &str = "authorization";
+const BEARER: &str = "Bearer ";
+
+/// The clients allowed to use the gRPC service, read from a TOML file:
+/// ```toml
+/// [[clients]]
+/// name = "desk-a"
+/// token = "<secret>"
+/// symbols = ["ETH/BTC"]
+/// exchanges = ["binance", "bitstamp"]
+/// max_depth = 10
+/// max_streams = 2
+/// admin = false
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Credentials {
+    #[serde(default)]
+    pub clients: Vec<ClientCredentials>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientCredentials {
+    pub name: String,
+    pub token: String,
+    #[serde(flatten)]
+    pub entitlements: Entitlements,
+}
+
+/// What a client may request.  Unset limits are unrestricted.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Entitlements {
+    pub symbols: Option<Vec<String>>,
+    pub exchanges: Option<Vec<String>>,
+    /// The maximum number of levels of each side.
+    pub max_depth: Option<usize>,
+    /// The maximum number of concurrent streams.
+    pub max_streams: Option<u64>,
+    /// May use the admin service.
+    pub admin: bool,
+}
+
+/// An authenticated client, added to the request extensions by the [AuthInterceptor].
+#[derive(Clone, Debug)]
+pub struct AuthenticatedClient {
+    pub name: String,
+    pub entitlements: Entitlements,
+}
+
+impl Credentials {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read the credentials file '{}':  {}", path, err))?;
+        toml::from_str(&contents)
+            .map_err(|err| format!("Failed to parse the credentials file '{}':  {}", path, err))
+    }
+
+    fn authenticate(&self, token: &str) -> Option<&ClientCredentials> {
+        self.clients
+            .iter()
+            .find(|client| constant_time_eq(client.token.as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Compare secrets without leaking how much of them matched through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// `Status` is large, but it is what the checks' callers return.
+#[allow(clippy::result_large_err)]
+impl Entitlements {
+    /// Check a request for the symbol against the entitlements.
+    pub fn check_symbol(&self, symbol: &Instrument) -> Result<(), Status> {
+        match &self.symbols {
+            Some(symbols)
+                if !symbols
+                    .iter()
+                    .any(|s| s.parse::<Instrument>().as_ref() == Ok(symbol)) =>
+            {
+                Err(Status::permission_denied(format!(
+                    "Not entitled to {}.",
+                    symbol
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a request for the exchanges against the entitlements.
+    /// An empty request is for every exchange the client is entitled to.
+    /// Returns the exchanges to include, or `None` for every exchange.
+    pub fn check_exchanges(&self, requested: &[String]) -> Result<Option<HashSet<String>>, Status> {
+        match (&self.exchanges, requested.is_empty()) {
+            (None, true) => Ok(None),
+            (None, false) => Ok(Some(requested.iter().cloned().collect())),
+            (Some(allowed), true) => Ok(Some(allowed.iter().cloned().collect())),
+            (Some(allowed), false) => match requested.iter().find(|e| !allowed.contains(e)) {
+                Some(exchange) => Err(Status::permission_denied(format!(
+                    "Not entitled to {}.",
+                    exchange
+                ))),
+                None => Ok(Some(requested.iter().cloned().collect())),
+            },
+        }
+    }
+
+    /// Check a request for the depth against the entitlements.
+    /// A depth of `0` is for as many levels as the client is entitled to.
+    /// Returns the depth to send, or `None` for every level.
+    pub fn check_depth(&self, requested: usize) -> Result<Option<usize>, Status> {
+        match (self.max_depth, requested) {
+            (None, 0) => Ok(None),
+            (None, depth) => Ok(Some(depth)),
+            (Some(max_depth), 0) => Ok(Some(max_depth)),
+            (Some(max_depth), depth) if depth > max_depth => Err(Status::permission_denied(
+                format!("Not entitled to more than {} levels.", max_depth),
+            )),
+            (Some(_), depth) => Ok(Some(depth)),
+        }
+    }
+}
+
+/// Authenticates the bearer token of each request against the credentials.
+/// Without credentials every request is allowed.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    credentials: Option<Arc<Credentials>>,
+}
+
+impl AuthInterceptor {
+    pub fn new(credentials: Option<Credentials>) -> Self {
+        Self {
+            credentials: credentials.map(Arc::new),
+        }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let credentials = match &self.credentials {
+            None => return Ok(request),
+            Some(credentials) => credentials,
+        };
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER))
+            .ok_or_else(|| Status::unauthenticated("A bearer token is required."))?;
+        let client = credentials.authenticate(token).ok_or_else(|| {
+            warn!("Rejected an invalid token from {:?}", request.remote_addr());
+            Status::unauthenticated("Invalid token.")
+        })?;
+
+        let client = AuthenticatedClient {
+            name: client.name.clone(),
+            entitlements: client.entitlements.clone(),
+        };
+        request.extensions_mut().insert(client);
+        Ok(request)
+    }
+}
+
+/// The authenticated client of a request, if authentication is enabled.
+pub fn authenticated_client<T>(request: &Request<T>) -> Option<&AuthenticatedClient> {
+    request.extensions().get::<AuthenticatedClient>()
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::service::Interceptor;
+    use tonic::{Code, Request};
+
+    use super::{authenticated_client, AuthInterceptor, Credentials, Entitlements};
+    use crate::common::instrument::Instrument;
+
+    const CREDENTIALS: &str = r#"
+        [[clients]]
+        name = "desk-a"
+        token = "secret-a"
+        symbols = ["ETH/BTC"]
+        exchanges = ["binance"]
+        max_depth = 5
+        max_streams = 1
+        admin = true
+
+        [[clients]]
+        name = "desk-b"
+        token = "secret-b"
+    "#;
+
+    fn request_with_token(token: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        request
+    }
+
+    #[test]
+    fn authenticate_tokens() {
+        let credentials: Credentials = toml::from_str(CREDENTIALS).unwrap();
+        let mut interceptor = AuthInterceptor::new(Some(credentials));
+
+        let request = interceptor.call(request_with_token("secret-a")).unwrap();
+        let client = authenticated_client(&request).unwrap();
+        assert_eq!("desk-a", client.name);
+        assert_eq!(Some(1), client.entitlements.max_streams);
+        assert!(client.entitlements.admin);
+
+        let request = interceptor.call(request_with_token("secret-b")).unwrap();
+        assert_eq!(
+            Entitlements::default(),
+            authenticated_client(&request).unwrap().entitlements
+        );
+
+        let err = interceptor
+            .call(request_with_token("secret-c"))
+            .unwrap_err();
+        assert_eq!(Code::Unauthenticated, err.code());
+        let err = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(Code::Unauthenticated, err.code());
+    }
+
+    #[test]
+    fn check_entitlements() {
+        let credentials: Credentials = toml::from_str(CREDENTIALS).unwrap();
+        let entitlements = &credentials.clients[0].entitlements;
+
+        assert!(entitlements
+            .check_symbol(&Instrument::spot("ETH", "BTC"))
+            .is_ok());
+        let err = entitlements
+            .check_symbol(&Instrument::spot("BTC", "USD"))
+            .unwrap_err();
+        assert_eq!(Code::PermissionDenied, err.code());
+
+        let exchanges = entitlements.check_exchanges(&[]).unwrap().unwrap();
+        assert!(exchanges.contains("binance") && exchanges.len() == 1);
+        let err = entitlements
+            .check_exchanges(&["bitstamp".to_string()])
+            .unwrap_err();
+        assert_eq!(Code::PermissionDenied, err.code());
+
+        assert_eq!(Some(5), entitlements.check_depth(0).unwrap());
+        assert_eq!(Some(3), entitlements.check_depth(3).unwrap());
+        assert_eq!(
+            Code::PermissionDenied,
+            entitlements.check_depth(6).unwrap_err().code()
+        );
+        assert_eq!(None, Entitlements::default().check_depth(0).unwrap());
+    }
+}
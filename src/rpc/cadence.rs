@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use tonic::Status;
+
+use crate::proto;
+
+/// How often a client is sent updates, as requested in its `SummaryRequest`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cadence {
+    /// The minimum time between updates.  Updates in between are conflated.
+    pub min_interval: Duration,
+    /// Only send updates which change the top of the book.
+    pub top_of_book: bool,
+}
+
+impl Cadence {
+    /// The cadence of the request, combining its maximum update rate and its sampling.
+    #[allow(clippy::result_large_err)]
+    pub fn from_request(request: &proto::SummaryRequest) -> Result<Self, Status> {
+        let max_rate_interval = match request.max_rate {
+            0.0 => Duration::ZERO,
+            rate if rate > 0.0 && rate.is_finite() => Duration::from_secs_f64(1.0 / rate),
+            _ => {
+                return Err(Status::invalid_argument(
+                    "The maximum update rate must be positive.",
+                ))
+            }
+        };
+        let cadence = match proto::Cadence::from_i32(request.cadence) {
+            None => return Err(Status::invalid_argument("Unknown cadence.")),
+            Some(proto::Cadence::EveryUpdate) => Self {
+                min_interval: Duration::ZERO,
+                top_of_book: false,
+            },
+            Some(proto::Cadence::Interval) if request.interval_ms == 0 => {
+                return Err(Status::invalid_argument(
+                    "The INTERVAL cadence requires a positive interval_ms.",
+                ))
+            }
+            Some(proto::Cadence::Interval) => Self {
+                min_interval: Duration::from_millis(request.interval_ms.into()),
+                top_of_book: false,
+            },
+            Some(proto::Cadence::TopOfBook) => Self {
+                min_interval: Duration::ZERO,
+                top_of_book: true,
+            },
+        };
+        Ok(Self {
+            min_interval: cadence.min_interval.max(max_rate_interval),
+            ..cadence
+        })
+    }
+}
+
+/// The best bid and ask prices and sizes.
+type TopOfBook = [Option<(f64, f64)>; 2];
+
+fn top_of_book(summary: &proto::Summary) -> TopOfBook {
+    [summary.bids.first(), summary.asks.first()]
+        .map(|level| level.map(|level| (level.price, level.amount)))
+}
+
+/// Decides which of the conflated updates are sent to a client.
+#[derive(Debug)]
+pub struct Sampler {
+    cadence: Cadence,
+    /// The top of the book of the latest update sent.
+    last_sent: Option<TopOfBook>,
+}
+
+impl Sampler {
+    pub fn new(cadence: Cadence) -> Self {
+        Self {
+            cadence,
+            last_sent: None,
+        }
+    }
+
+    /// Whether to send the update.
+    pub fn should_send(&self, summary: &proto::Summary) -> bool {
+        !self.cadence.top_of_book || self.last_sent != Some(top_of_book(summary))
+    }
+
+    /// Record that the update has been sent.
+    pub fn mark_sent(&mut self, summary: &proto::Summary) {
+        self.last_sent = Some(top_of_book(summary));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tonic::Code;
+
+    use super::{Cadence, Sampler};
+    use crate::common::fixtures::level;
+    use crate::proto;
+
+    fn summary(bid: (f64, f64), ask: (f64, f64), depth: usize) -> proto::Summary {
+        proto::Summary {
+            spread: ask.0 - bid.0,
+            bids: vec![level("binance", bid.0, bid.1); depth],
+            asks: vec![level("binance", ask.0, ask.1); depth],
+        }
+    }
+
+    #[test]
+    fn cadence_from_request() {
+        let request = proto::SummaryRequest {
+            cadence: proto::Cadence::Interval as i32,
+            interval_ms: 250,
+            max_rate: 2.0,
+            ..Default::default()
+        };
+        let cadence = Cadence::from_request(&request).unwrap();
+        assert_eq!(Duration::from_millis(500), cadence.min_interval);
+        assert!(!cadence.top_of_book);
+
+        let request = proto::SummaryRequest {
+            cadence: proto::Cadence::Interval as i32,
+            ..Default::default()
+        };
+        let err = Cadence::from_request(&request).unwrap_err();
+        assert_eq!(Code::InvalidArgument, err.code());
+
+        let request = proto::SummaryRequest {
+            cadence: 7,
+            ..Default::default()
+        };
+        assert!(Cadence::from_request(&request).is_err());
+    }
+
+    #[test]
+    fn sample_top_of_book_changes() {
+        let mut sampler = Sampler::new(Cadence {
+            min_interval: Duration::ZERO,
+            top_of_book: true,
+        });
+        let sample = |sampler: &mut Sampler, summary: &proto::Summary| {
+            let should_send = sampler.should_send(summary);
+            if should_send {
+                sampler.mark_sent(summary);
+            }
+            should_send
+        };
+        assert!(sample(&mut sampler, &summary((1.0, 2.0), (1.1, 3.0), 2)));
+        // Only the depth changed.
+        assert!(!sample(&mut sampler, &summary((1.0, 2.0), (1.1, 3.0), 5)));
+        // The best ask's size changed.
+        assert!(sample(&mut sampler, &summary((1.0, 2.0), (1.1, 4.0), 5)));
+        assert!(sample(&mut sampler, &summary((0.9, 2.0), (1.1, 4.0), 5)));
+
+        let mut sampler = Sampler::new(Cadence {
+            min_interval: Duration::ZERO,
+            top_of_book: false,
+        });
+        assert!(sample(&mut sampler, &summary((1.0, 2.0), (1.1, 3.0), 2)));
+        assert!(sample(&mut sampler, &summary((1.0, 2.0), (1.1, 3.0), 2)));
+    }
+
+    #[test]
+    fn resample_unsent_top_of_book() {
+        let mut sampler = Sampler::new(Cadence {
+            min_interval: Duration::ZERO,
+            top_of_book: true,
+        });
+        sampler.mark_sent(&summary((1.0, 2.0), (1.1, 3.0), 2));
+        // The client's channel is full, so the change is not sent.
+        let changed = summary((1.0, 2.0), (1.1, 4.0), 2);
+        assert!(sampler.should_send(&changed));
+        // The next update leaves the top of the book as it was changed, but is still sent.
+        assert!(sampler.should_send(&summary((1.0, 2.0), (1.1, 4.0), 5)));
+        sampler.mark_sent(&changed);
+        assert!(!sampler.should_send(&summary((1.0, 2.0), (1.1, 4.0), 5)));
+    }
+}
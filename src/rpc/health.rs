@@ -0,0 +1,108 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::proto::orderbook_aggregator_server::OrderbookAggregatorServer;
+use crate::rpc::server::OrderbookAggregatorService;
+use crate::state::MarketState;
+
+/// How often exchange liveness is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Report the order-book service as serving while enough exchanges are live.
+pub async fn report_exchange_liveness(
+    mut reporter: HealthReporter,
+    state: Arc<MarketState>,
+    min_live_exchanges: usize,
+) {
+    let mut liveness = Liveness::new(min_live_exchanges);
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let status = match liveness.check(&state) {
+            Some(status) => status,
+            None => continue,
+        };
+        // The empty service name is the overall health of the server.
+        reporter.set_service_status("", status).await;
+        if status == ServingStatus::Serving {
+            reporter
+                .set_serving::<OrderbookAggregatorServer<OrderbookAggregatorService>>()
+                .await;
+        } else {
+            reporter
+                .set_not_serving::<OrderbookAggregatorServer<OrderbookAggregatorService>>()
+                .await;
+        }
+    }
+}
+
+/// Whether enough exchanges are live to serve.
+struct Liveness {
+    min_live_exchanges: usize,
+    /// The latest status reported.
+    serving: Option<bool>,
+}
+
+impl Liveness {
+    fn new(min_live_exchanges: usize) -> Self {
+        Self {
+            min_live_exchanges,
+            serving: None,
+        }
+    }
+
+    /// The serving status, if it has changed since the last check.
+    fn check(&mut self, state: &MarketState) -> Option<ServingStatus> {
+        let live_exchanges = state.live_exchanges();
+        let is_serving = live_exchanges >= self.min_live_exchanges;
+        if self.serving == Some(is_serving) {
+            return None;
+        }
+        self.serving = Some(is_serving);
+
+        Some(if is_serving {
+            info!("{} exchanges are live.  Serving.", live_exchanges);
+            ServingStatus::Serving
+        } else {
+            warn!(
+                "{} exchanges are live, {} are required.  Not serving.",
+                live_exchanges, self.min_live_exchanges
+            );
+            ServingStatus::NotServing
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic_health::ServingStatus;
+
+    use super::Liveness;
+    use crate::state::{ConnectionStatus, MarketState};
+
+    #[test]
+    fn serve_while_exchanges_are_live() {
+        let state = MarketState::default();
+        let mut liveness = Liveness::new(2);
+        assert_eq!(Some(ServingStatus::NotServing), liveness.check(&state));
+
+        state.book_updated("binance");
+        // Only changes are reported.
+        assert_eq!(None, liveness.check(&state));
+        state.book_updated("bitstamp");
+        assert_eq!(Some(ServingStatus::Serving), liveness.check(&state));
+        assert_eq!(None, liveness.check(&state));
+
+        state.set_status(
+            "bitstamp",
+            ConnectionStatus::Disconnected,
+            "The stream failed.",
+            None,
+        );
+        assert_eq!(Some(ServingStatus::NotServing), liveness.check(&state));
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tonic::Status;
+
+/// Peers without streams are forgotten once their last stream is this old.
+const FORGET_PEERS_AFTER: Duration = Duration::from_secs(60);
+
+/// Caps on the gRPC streams, to protect the server from misbehaving clients.
+/// Unset caps are unlimited.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StreamLimits {
+    /// The maximum number of concurrent streams of all peers.
+    pub max_streams: Option<usize>,
+    /// The maximum number of concurrent streams of each peer.
+    pub max_streams_per_peer: Option<usize>,
+    /// The maximum number of new streams per second of each peer.
+    pub new_stream_rate: Option<f64>,
+}
+
+#[derive(Debug)]
+struct PeerStreams {
+    active: usize,
+    /// Token bucket of new streams.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Streams {
+    active: usize,
+    /// `<peer-address> => <peer-streams>`
+    peers: HashMap<IpAddr, PeerStreams>,
+}
+
+/// Counts the active streams of each peer against the [StreamLimits].
+#[derive(Debug, Default)]
+pub struct StreamLimiter {
+    limits: StreamLimits,
+    streams: Arc<Mutex<Streams>>,
+}
+
+/// An accepted stream, which is released when dropped.
+#[derive(Debug)]
+pub struct StreamPermit {
+    peer: IpAddr,
+    streams: Arc<Mutex<Streams>>,
+}
+
+impl StreamLimiter {
+    pub fn new(limits: StreamLimits) -> Self {
+        Self {
+            limits,
+            streams: Default::default(),
+        }
+    }
+
+    /// Accept a new stream from the peer, or reject it with `RESOURCE_EXHAUSTED`.
+    /// Peers are identified by IP address, so reconnecting from a new port
+    /// does not reset their limits.
+    #[allow(clippy::result_large_err)]
+    pub fn acquire(&self, peer: Option<IpAddr>) -> Result<StreamPermit, Status> {
+        let peer = peer.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let mut streams = self.streams.lock().unwrap();
+        if self
+            .limits
+            .max_streams
+            .is_some_and(|max| streams.active >= max)
+        {
+            return Err(Status::resource_exhausted(
+                "The server has too many streams.",
+            ));
+        }
+
+        let now = Instant::now();
+        let burst = self
+            .limits
+            .new_stream_rate
+            .map_or(0.0, |rate| rate.max(1.0));
+        let peer_streams = streams.peers.entry(peer).or_insert(PeerStreams {
+            active: 0,
+            tokens: burst,
+            refilled_at: now,
+        });
+        if self
+            .limits
+            .max_streams_per_peer
+            .is_some_and(|max| peer_streams.active >= max)
+        {
+            return Err(Status::resource_exhausted(
+                "Too many concurrent streams from this address.",
+            ));
+        }
+        if let Some(rate) = self.limits.new_stream_rate {
+            let elapsed = now.duration_since(peer_streams.refilled_at).as_secs_f64();
+            peer_streams.tokens = (peer_streams.tokens + elapsed * rate).min(burst);
+            peer_streams.refilled_at = now;
+            if peer_streams.tokens < 1.0 {
+                return Err(Status::resource_exhausted(
+                    "Too many new streams from this address.  Slow down.",
+                ));
+            }
+            peer_streams.tokens -= 1.0;
+        }
+
+        peer_streams.active += 1;
+        streams.active += 1;
+        Ok(StreamPermit {
+            peer,
+            streams: self.streams.clone(),
+        })
+    }
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.active -= 1;
+        if let Some(peer_streams) = streams.peers.get_mut(&self.peer) {
+            peer_streams.active -= 1;
+        }
+        streams.peers.retain(|_, peer_streams| {
+            peer_streams.active > 0 || peer_streams.refilled_at.elapsed() < FORGET_PEERS_AFTER
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tonic::Code;
+
+    use super::{StreamLimiter, StreamLimits};
+
+    const PEER_A: Option<IpAddr> = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+    const PEER_B: Option<IpAddr> = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+    #[test]
+    fn limit_concurrent_streams() {
+        let limiter = StreamLimiter::new(StreamLimits {
+            max_streams: Some(3),
+            max_streams_per_peer: Some(2),
+            new_stream_rate: None,
+        });
+        let a1 = limiter.acquire(PEER_A).unwrap();
+        let _a2 = limiter.acquire(PEER_A).unwrap();
+        let err = limiter.acquire(PEER_A).unwrap_err();
+        assert_eq!(Code::ResourceExhausted, err.code());
+
+        let _b1 = limiter.acquire(PEER_B).unwrap();
+        assert!(limiter.acquire(PEER_B).is_err());
+
+        drop(a1);
+        assert!(limiter.acquire(PEER_B).is_ok());
+    }
+
+    #[test]
+    fn throttle_new_streams() {
+        let limiter = StreamLimiter::new(StreamLimits {
+            max_streams: None,
+            max_streams_per_peer: None,
+            new_stream_rate: Some(2.0),
+        });
+        // Streams which have ended still count against the rate.
+        assert!(limiter.acquire(PEER_A).is_ok());
+        assert!(limiter.acquire(PEER_A).is_ok());
+        let err = limiter.acquire(PEER_A).unwrap_err();
+        assert_eq!(Code::ResourceExhausted, err.code());
+        assert!(limiter.acquire(PEER_B).is_ok());
+    }
+}
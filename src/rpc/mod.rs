@@ -1 +1,7 @@
+pub mod admin;
+pub mod auth;
+pub mod cadence;
+pub mod health;
+pub mod limits;
 pub mod server;
+pub mod tls;
@@ -1,24 +1,376 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
 use async_trait::async_trait;
-use log::info;
+use itertools::Itertools;
+use log::{info, warn};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::error::TrySendError::{Closed, Full};
 use tokio::sync::{broadcast, mpsc};
+use tokio::time::{self, Instant};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
 use proto::orderbook_aggregator_server::OrderbookAggregator;
 
+use crate::alerts::SentAlerts;
+use crate::analytics;
+use crate::arbitrage;
+use crate::common::instrument::Instrument;
+use crate::impact::{self, Quantity};
+use crate::merger::MergedBook;
 use crate::proto;
+use crate::routing::{self, ExchangeTerms, ParentOrder};
+use crate::rpc::auth::{authenticated_client, Entitlements};
+use crate::rpc::cadence::{Cadence, Sampler};
+use crate::rpc::limits::{StreamLimiter, StreamLimits, StreamPermit};
+use crate::rpc::tls::{client_identity, peer_identity};
+use crate::state::{ConnectionStatus, ExchangeState, MarketState, SequencedBook, StatusEvent};
+
+/// How often the exchange order-book streams check for status changes.
+const STATUS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How soon an update is retried when the client's stream is full.
+const FULL_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The default number of levels of each side of the weighted mid and the imbalance.
+const DEFAULT_ANALYTICS_LEVELS: usize = 5;
 
 pub struct OrderbookAggregatorService {
+    /// The symbol being merged.
+    symbol: Instrument,
+    /// The exchanges being merged.
+    exchanges: Vec<&'static str>,
     /// Subscribe to this broadcast channel for the merged order-book stream.
-    broadcast_tx: broadcast::Sender<proto::Summary>,
+    broadcast_tx: broadcast::Sender<MergedBook>,
+    /// Subscribe to this broadcast channel for the exchanges' order-book streams.
+    exchange_books_tx: broadcast::Sender<SequencedBook>,
+    state: Arc<MarketState>,
+    limiter: StreamLimiter,
+    /// The exchanges' fees and order size limits, for routing.
+    /// `<exchange-name> => <exchange-terms>`
+    exchange_terms: HashMap<String, ExchangeTerms>,
+}
+
+/// An open stream of a client.  It is counted in the market state until dropped.
+struct ClientStream {
+    client: String,
+    remote_addr: Option<SocketAddr>,
+    state: Arc<MarketState>,
+    _permit: StreamPermit,
+}
+
+impl Drop for ClientStream {
+    fn drop(&mut self) {
+        info!(
+            "Client '{}' disconnected from {:?}",
+            self.client, self.remote_addr
+        );
+        self.state.client_disconnected(&self.client);
+    }
+}
+
+/// The identity and entitlements of the request's client.
+/// Without authentication every client is entitled to everything.
+fn client_of<T>(request: &Request<T>) -> (String, Entitlements) {
+    match authenticated_client(request) {
+        Some(client) => (
+            client_name(&client.name, peer_identity(request).as_deref()),
+            client.entitlements.clone(),
+        ),
+        None => (client_identity(request), Entitlements::default()),
+    }
+}
+
+/// The name of an authenticated client, followed by the identity of its certificate if it presented one.
+fn client_name(token_name: &str, certificate: Option<&str>) -> String {
+    match certificate {
+        Some(certificate) => format!("{} ({})", token_name, certificate),
+        None => token_name.to_string(),
+    }
+}
+
+impl From<ConnectionStatus> for proto::ConnectionStatus {
+    fn from(status: ConnectionStatus) -> Self {
+        match status {
+            ConnectionStatus::Connecting => proto::ConnectionStatus::Connecting,
+            ConnectionStatus::Subscribed => proto::ConnectionStatus::Subscribed,
+            ConnectionStatus::Live => proto::ConnectionStatus::Live,
+            ConnectionStatus::Stale => proto::ConnectionStatus::Stale,
+            ConnectionStatus::Disconnected => proto::ConnectionStatus::Disconnected,
+            ConnectionStatus::Reconnecting => proto::ConnectionStatus::Reconnecting,
+            ConnectionStatus::Quarantined => proto::ConnectionStatus::Quarantined,
+            ConnectionStatus::Disabled => proto::ConnectionStatus::Disabled,
+        }
+    }
+}
+
+impl From<StatusEvent> for proto::ExchangeStatusEvent {
+    fn from(event: StatusEvent) -> Self {
+        proto::ExchangeStatusEvent {
+            exchange: event.exchange.to_string(),
+            status: proto::ConnectionStatus::from(event.status) as i32,
+            timestamp_ns: event
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_nanos() as u64),
+            reason: event.reason,
+            last_error: event.last_error.unwrap_or_default(),
+        }
+    }
+}
+
+/// The connection status of an exchange, which is connecting until its state is known.
+fn connection_status(state: Option<&ExchangeState>) -> proto::ConnectionStatus {
+    state.map_or(proto::ConnectionStatus::Connecting, |state| {
+        state.status.into()
+    })
+}
+
+/// The analytics of every level of the given exchanges' order-books, merged,
+/// and of each of their order-books, limited to the top `depth` levels of each.
+fn merged_book_analytics(
+    merged_book: &MergedBook,
+    exchanges: Option<&HashSet<String>>,
+    levels: usize,
+    depth: usize,
+) -> proto::BookAnalytics {
+    let summary = merged_book.every_level_of(exchanges).with_depth(depth);
+    let merged = analytics::analyze("", &summary.bids, &summary.asks, levels);
+    let exchange_analytics = merged_book
+        .order_books
+        .values()
+        .filter(|order_book| {
+            exchanges.is_none_or(|exchanges| exchanges.contains(order_book.exchange))
+        })
+        .sorted_by_key(|order_book| order_book.exchange)
+        .filter_map(|order_book| {
+            let bids = &order_book.bids[..depth.min(order_book.bids.len())];
+            let asks = &order_book.asks[..depth.min(order_book.asks.len())];
+            analytics::analyze(order_book.exchange, bids, asks, levels)
+        })
+        .collect();
+    proto::BookAnalytics {
+        merged,
+        exchanges: exchange_analytics,
+    }
+}
+
+fn exchange_order_book(
+    exchange: &str,
+    book: Option<&SequencedBook>,
+    status: proto::ConnectionStatus,
+    depth: Option<usize>,
+) -> proto::ExchangeOrderBook {
+    let depth = depth.unwrap_or(usize::MAX);
+    let (sequence, bids, asks) = match book {
+        None => (0, vec![], vec![]),
+        Some(book) => (
+            book.sequence,
+            book.order_book.bids.iter().take(depth).cloned().collect(),
+            book.order_book
+                .asks
+                .iter()
+                .take(depth)
+                .cloned()
+                .collect::<Vec<_>>(),
+        ),
+    };
+    let spread = match (bids.first(), asks.first()) {
+        (Some(bid), Some(ask)) => ask.price - bid.price,
+        _ => 0.0,
+    };
+    proto::ExchangeOrderBook {
+        exchange: exchange.to_string(),
+        sequence,
+        status: status as i32,
+        spread,
+        bids,
+        asks,
+    }
+}
+
+/// What a stream does when its client does not keep up.
+#[derive(Clone, Copy, Debug)]
+enum Overflow {
+    /// Skip the message, since a later message supersedes it.
+    Skip,
+    /// Close the stream with the error message, since the client must not miss a message.
+    Close(&'static str),
+}
+
+/// Stream the `initial` messages, and then each message of the source as `map` turns it
+/// into the client's message.  `map` is also called with `None` on each of the `ticks`, if any.
+/// Messages which `map` leaves out, or which equal the last message sent, are not sent.
+/// The client's stream is held until the stream ends.
+fn forward<T, M, F>(
+    mut source: broadcast::Receiver<T>,
+    initial: Vec<M>,
+    capacity: usize,
+    ticks: Option<Duration>,
+    overflow: Overflow,
+    client_stream: ClientStream,
+    mut map: F,
+) -> ReceiverStream<Result<M, Status>>
+where
+    T: Clone + Send + 'static,
+    M: Clone + PartialEq + Send + 'static,
+    F: FnMut(Option<T>) -> Option<M> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(capacity);
+    let mut last_sent = None;
+    for message in initial {
+        if tx.try_send(Ok(message.clone())).is_ok() {
+            last_sent = Some(message);
+        }
+    }
+
+    tokio::spawn(async move {
+        let mut interval = ticks.map(time::interval);
+        loop {
+            let received = tokio::select! {
+                received = source.recv() => match received {
+                    Ok(message) => Some(message),
+                    Err(RecvError::Lagged(_)) => match overflow {
+                        Overflow::Skip => continue,
+                        Overflow::Close(message) => {
+                            tx.send(Err(Status::resource_exhausted(message))).await.unwrap_or(());
+                            break;
+                        }
+                    },
+                    Err(RecvError::Closed) => break,
+                },
+                Some(_) = async {
+                    match interval.as_mut() {
+                        Some(interval) => Some(interval.tick().await),
+                        None => None,
+                    }
+                } => None,
+                // The client has disconnected.
+                _ = tx.closed() => break,
+            };
+            let message = match map(received) {
+                Some(message) if last_sent.as_ref() != Some(&message) => message,
+                _ => continue,
+            };
+            match tx.try_send(Ok(message.clone())) {
+                Ok(_) => last_sent = Some(message),
+                Err(err) => match (err, overflow) {
+                    // The receiver channel is full.
+                    (Full(_), Overflow::Skip) => continue,
+                    (Full(_), Overflow::Close(message)) => {
+                        tx.send(Err(Status::resource_exhausted(message)))
+                            .await
+                            .unwrap_or(());
+                        break;
+                    }
+                    // The client has disconnected.
+                    (Closed(_), _) => break,
+                },
+            }
+        }
+        drop(client_stream);
+    });
+    ReceiverStream::new(rx)
 }
 
 impl OrderbookAggregatorService {
-    pub fn new(channel: broadcast::Sender<proto::Summary>) -> Self {
+    pub fn new(
+        symbol: Instrument,
+        exchanges: Vec<&'static str>,
+        channel: broadcast::Sender<MergedBook>,
+        exchange_books_tx: broadcast::Sender<SequencedBook>,
+        state: Arc<MarketState>,
+        limits: StreamLimits,
+        exchange_terms: HashMap<String, ExchangeTerms>,
+    ) -> Self {
         Self {
+            symbol,
+            exchanges,
             broadcast_tx: channel,
+            exchange_books_tx,
+            state,
+            limiter: StreamLimiter::new(limits),
+            exchange_terms,
+        }
+    }
+
+    /// Every level of the exchanges' latest order-books, merged,
+    /// limited to the exchanges and depth the client is entitled to.
+    #[allow(clippy::result_large_err)]
+    fn entitled_summary(
+        &self,
+        entitlements: &Entitlements,
+        exchanges: &[String],
+    ) -> Result<proto::Summary, Status> {
+        let exchanges = entitlements.check_exchanges(exchanges)?;
+        let depth = entitlements.check_depth(0)?;
+        let mut summary = self
+            .state
+            .latest()
+            .map(|merged_book| merged_book.every_level_of(exchanges.as_ref()))
+            .unwrap_or_default();
+        if let Some(depth) = depth {
+            summary = summary.with_depth(depth);
         }
+        Ok(summary)
+    }
+
+    /// Check that the requested symbol is served, and that the client is entitled to it.
+    /// An empty symbol is the served symbol.
+    #[allow(clippy::result_large_err)]
+    fn check_symbol(&self, entitlements: &Entitlements, symbol: &str) -> Result<(), Status> {
+        if !symbol.is_empty() {
+            let symbol = symbol
+                .parse::<Instrument>()
+                .map_err(Status::invalid_argument)?;
+            entitlements.check_symbol(&symbol)?;
+            if symbol != self.symbol {
+                return Err(Status::not_found(format!("{} is not served.", symbol)));
+            }
+        }
+        entitlements.check_symbol(&self.symbol)
+    }
+
+    /// Open a stream of the client, unless it would exceed the stream limits
+    /// or the client's entitlements.
+    #[allow(clippy::result_large_err)]
+    fn open_stream(
+        &self,
+        client: String,
+        entitlements: &Entitlements,
+        remote_addr: Option<SocketAddr>,
+    ) -> Result<ClientStream, Status> {
+        let permit = self
+            .limiter
+            .acquire(remote_addr.map(|addr| addr.ip()))
+            .inspect_err(|status| {
+                warn!(
+                    "Rejected a stream of client '{}' from {:?}:  {}",
+                    client,
+                    remote_addr,
+                    status.message()
+                );
+            })?;
+        if !self
+            .state
+            .client_connected(&client, entitlements.max_streams)
+        {
+            warn!(
+                "Client '{}' from {:?} exceeded its concurrent stream limit.",
+                client, remote_addr
+            );
+            return Err(Status::permission_denied("Too many concurrent streams."));
+        }
+        info!("Client '{}' connected from {:?}", client, remote_addr);
+        Ok(ClientStream {
+            client,
+            remote_addr,
+            state: self.state.clone(),
+            _permit: permit,
+        })
     }
 }
 
@@ -28,28 +380,671 @@ impl OrderbookAggregator for OrderbookAggregatorService {
 
     async fn book_summary(
         &self,
-        request: Request<proto::Empty>,
+        request: Request<proto::SummaryRequest>,
     ) -> Result<Response<Self::BookSummaryStream>, Status> {
-        info!("Client connected from {:?}", request.remote_addr());
+        let (client, entitlements) = client_of(&request);
+        let remote_addr = request.remote_addr();
+        let summary_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &summary_request.symbol)?;
+        let exchanges = entitlements.check_exchanges(&summary_request.exchanges)?;
+        let depth = entitlements.check_depth(summary_request.depth as usize)?;
+        let cadence = Cadence::from_request(&summary_request)?;
+        let fee_adjusted = summary_request.fee_adjusted;
+        let exchange_terms = self.exchange_terms.clone();
+        let filter = move |merged_book: MergedBook| {
+            let summary = if fee_adjusted {
+                merged_book.fee_adjusted(exchanges.as_ref(), &exchange_terms)
+            } else {
+                merged_book.of_exchanges(exchanges.as_ref())
+            };
+            match depth {
+                None => summary,
+                Some(depth) => summary.with_depth(depth),
+            }
+        };
+        let stream = self.open_stream(client, &entitlements, remote_addr)?;
 
         let (tx, rx) = mpsc::channel(10);
         let mut merged_order_books = self.broadcast_tx.subscribe();
+        // Start the stream with the latest merged order-book rather than
+        // waiting for the next update.
+        let mut sampler = Sampler::new(cadence);
+        if let Some(summary) = self.state.latest() {
+            let summary = filter(summary);
+            if tx.try_send(Ok(summary.clone())).is_ok() {
+                sampler.mark_sent(&summary);
+            }
+        }
 
+        // Unlike the other streams, the updates are paced by the cadence, so it is not forwarded.
         tokio::spawn(async move {
-            while let Ok(summary) = merged_order_books.recv().await {
-                match tx.try_send(Ok(summary)) {
-                    Ok(_) => { /* Pass */ }
-                    Err(err) => match err {
-                        // The receiver channel is full.
-                        Full(_) => continue,
-                        // The client has disconnected.
-                        Closed(_) => break,
+            // Conflate the updates between sends, so that only the latest is sent.
+            let mut pending = None;
+            let mut next_send = Instant::now() + cadence.min_interval;
+            loop {
+                tokio::select! {
+                    received = merged_order_books.recv() => match received {
+                        Ok(summary) => pending = Some(filter(summary)),
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
                     },
+                    _ = time::sleep_until(next_send), if pending.is_some() => {
+                        let summary = pending.take().unwrap();
+                        if !sampler.should_send(&summary) {
+                            continue;
+                        }
+                        match tx.try_send(Ok(summary.clone())) {
+                            Ok(_) => sampler.mark_sent(&summary),
+                            Err(err) => match err {
+                                // The receiver channel is full.  Retry unless a newer update arrives.
+                                Full(_) => {
+                                    pending = Some(summary);
+                                    next_send = Instant::now() + FULL_RETRY_INTERVAL;
+                                    continue;
+                                }
+                                // The client has disconnected.
+                                Closed(_) => break,
+                            },
+                        }
+                        next_send = Instant::now() + cadence.min_interval;
+                    },
+                    // The client has disconnected.
+                    _ = tx.closed() => break,
                 }
             }
-            info!("Client disconnected from {:?}", request.remote_addr())
+            drop(stream);
         });
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+
+    type BestBidOfferStream = ReceiverStream<Result<proto::TopOfBook, Status>>;
+
+    async fn best_bid_offer(
+        &self,
+        request: Request<proto::BestBidOfferRequest>,
+    ) -> Result<Response<Self::BestBidOfferStream>, Status> {
+        let (client, entitlements) = client_of(&request);
+        let remote_addr = request.remote_addr();
+        let bbo_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &bbo_request.symbol)?;
+        let exchanges = entitlements.check_exchanges(&bbo_request.exchanges)?;
+        let best_bid_offer = move |merged_book: MergedBook| {
+            merged_book
+                .of_exchanges(exchanges.as_ref())
+                .best_bid_offer()
+        };
+        let stream = self.open_stream(client, &entitlements, remote_addr)?;
+
+        // Only changes are sent.
+        let merged_order_books = self.broadcast_tx.subscribe();
+        let initial = self
+            .state
+            .latest()
+            .map(&best_bid_offer)
+            .into_iter()
+            .collect();
+        Ok(Response::new(forward(
+            merged_order_books,
+            initial,
+            10,
+            None,
+            Overflow::Skip,
+            stream,
+            move |merged_book| merged_book.map(&best_bid_offer),
+        )))
+    }
+
+    type ExchangeBookStream = ReceiverStream<Result<proto::ExchangeOrderBook, Status>>;
+
+    async fn exchange_book(
+        &self,
+        request: Request<proto::ExchangeBookRequest>,
+    ) -> Result<Response<Self::ExchangeBookStream>, Status> {
+        let (client, entitlements) = client_of(&request);
+        let remote_addr = request.remote_addr();
+        let book_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &book_request.symbol)?;
+        let exchange = self
+            .exchanges
+            .iter()
+            .copied()
+            .find(|exchange| *exchange == book_request.exchange)
+            .ok_or_else(|| {
+                Status::not_found(format!("Unknown exchange '{}'.", book_request.exchange))
+            })?;
+        entitlements.check_exchanges(&[book_request.exchange])?;
+        let depth = entitlements.check_depth(book_request.depth as usize)?;
+        let stream = self.open_stream(client, &entitlements, remote_addr)?;
+
+        let exchange_books = self.exchange_books_tx.subscribe();
+        let mut latest = self.state.exchange_book(exchange);
+        let state = self.state.clone();
+        let mut current = move |book: Option<SequencedBook>| {
+            if let Some(book) = book.filter(|book| book.order_book.exchange == exchange) {
+                latest = Some(book);
+            }
+            let status = connection_status(state.exchange(exchange).as_ref());
+            Some(exchange_order_book(
+                exchange,
+                latest.as_ref(),
+                status,
+                depth,
+            ))
+        };
+        let initial = current(None).into_iter().collect();
+        // Status changes are also sent, even without a new order-book.
+        Ok(Response::new(forward(
+            exchange_books,
+            initial,
+            10,
+            Some(STATUS_CHECK_INTERVAL),
+            Overflow::Skip,
+            stream,
+            current,
+        )))
+    }
+
+    type ExchangeStatusStream = ReceiverStream<Result<proto::ExchangeStatusEvent, Status>>;
+
+    async fn exchange_status(
+        &self,
+        request: Request<proto::ExchangeStatusRequest>,
+    ) -> Result<Response<Self::ExchangeStatusStream>, Status> {
+        let (client, entitlements) = client_of(&request);
+        let remote_addr = request.remote_addr();
+        let status_request = request.into_inner();
+
+        if let Some(exchange) = status_request
+            .exchanges
+            .iter()
+            .find(|exchange| !self.exchanges.contains(&exchange.as_str()))
+        {
+            return Err(Status::not_found(format!(
+                "Unknown exchange '{}'.",
+                exchange
+            )));
+        }
+        let exchanges = entitlements.check_exchanges(&status_request.exchanges)?;
+        let is_included = move |event: &StatusEvent| {
+            exchanges
+                .as_ref()
+                .is_none_or(|exchanges| exchanges.contains(event.exchange))
+        };
+        let stream = self.open_stream(client, &entitlements, remote_addr)?;
+
+        // Subscribe before reading the current statuses, so that no change is missed.
+        let status_events = self.state.subscribe_status();
+        let initial = self
+            .state
+            .status_events()
+            .into_iter()
+            .filter(&is_included)
+            .map(proto::ExchangeStatusEvent::from)
+            .collect();
+        Ok(Response::new(forward(
+            status_events,
+            initial,
+            100,
+            None,
+            Overflow::Skip,
+            stream,
+            move |event| event.filter(&is_included).map(Into::into),
+        )))
+    }
+
+    type ArbitrageStream = ReceiverStream<Result<proto::ArbitrageOpportunities, Status>>;
+
+    async fn arbitrage(
+        &self,
+        request: Request<proto::ArbitrageRequest>,
+    ) -> Result<Response<Self::ArbitrageStream>, Status> {
+        let (client, entitlements) = client_of(&request);
+        let remote_addr = request.remote_addr();
+        let arbitrage_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &arbitrage_request.symbol)?;
+        let exchanges = entitlements.check_exchanges(&arbitrage_request.exchanges)?;
+        let min_profit = arbitrage_request.min_profit;
+        if !min_profit.is_finite() {
+            return Err(Status::invalid_argument(
+                "The minimum profit must be a number.",
+            ));
+        }
+        let exchange_terms = self.exchange_terms.clone();
+        let opportunities = move |merged_book: MergedBook| {
+            let opportunities =
+                arbitrage::find_opportunities(&merged_book.order_books, &exchange_terms)
+                    .into_iter()
+                    .filter(|opportunity| opportunity.net_profit >= min_profit)
+                    .filter(|opportunity| {
+                        exchanges.as_ref().is_none_or(|exchanges| {
+                            exchanges.contains(&opportunity.buy_exchange)
+                                && exchanges.contains(&opportunity.sell_exchange)
+                        })
+                    })
+                    .collect();
+            proto::ArbitrageOpportunities { opportunities }
+        };
+        let stream = self.open_stream(client, &entitlements, remote_addr)?;
+
+        // The opportunities are updated with each merged order-book, and only changes are sent.
+        let merged_order_books = self.broadcast_tx.subscribe();
+        let initial = self
+            .state
+            .latest()
+            .map(&opportunities)
+            .into_iter()
+            .collect();
+        Ok(Response::new(forward(
+            merged_order_books,
+            initial,
+            10,
+            None,
+            Overflow::Skip,
+            stream,
+            move |merged_book| merged_book.map(&opportunities),
+        )))
+    }
+
+    type AnalyticsStream = ReceiverStream<Result<proto::BookAnalytics, Status>>;
+
+    async fn analytics(
+        &self,
+        request: Request<proto::AnalyticsRequest>,
+    ) -> Result<Response<Self::AnalyticsStream>, Status> {
+        let (client, entitlements) = client_of(&request);
+        let remote_addr = request.remote_addr();
+        let analytics_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &analytics_request.symbol)?;
+        let exchanges = entitlements.check_exchanges(&analytics_request.exchanges)?;
+        let levels = entitlements
+            .check_depth(analytics_request.levels as usize)?
+            .unwrap_or(DEFAULT_ANALYTICS_LEVELS);
+        // The depth bands only include the levels the client is entitled to see.
+        let depth = entitlements.check_depth(0)?.unwrap_or(usize::MAX);
+        let book_analytics = move |merged_book: MergedBook| {
+            merged_book_analytics(&merged_book, exchanges.as_ref(), levels, depth)
+        };
+        let stream = self.open_stream(client, &entitlements, remote_addr)?;
+
+        // Only changes are sent.
+        let merged_order_books = self.broadcast_tx.subscribe();
+        let initial = self
+            .state
+            .latest()
+            .map(&book_analytics)
+            .into_iter()
+            .collect();
+        Ok(Response::new(forward(
+            merged_order_books,
+            initial,
+            10,
+            None,
+            Overflow::Skip,
+            stream,
+            move |merged_book| merged_book.map(&book_analytics),
+        )))
+    }
+
+    async fn spread_stats(
+        &self,
+        request: Request<proto::SpreadStatsRequest>,
+    ) -> Result<Response<proto::SpreadStatistics>, Status> {
+        let (_, entitlements) = client_of(&request);
+        let stats_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &stats_request.symbol)?;
+        // The merged best bid and offer are recorded across every exchange.
+        if entitlements.check_exchanges(&[])?.is_some() {
+            return Err(Status::permission_denied(
+                "The spread statistics include every exchange.",
+            ));
+        }
+        let window = match stats_request.window_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs.into())),
+        };
+        let statistics = self.state.spread_statistics(window).ok_or_else(|| {
+            Status::failed_precondition("No best bid and offer has been merged yet.")
+        })?;
+        Ok(Response::new(statistics))
+    }
+
+    type AlertsStream = ReceiverStream<Result<proto::Alert, Status>>;
+
+    async fn alerts(
+        &self,
+        request: Request<proto::AlertsRequest>,
+    ) -> Result<Response<Self::AlertsStream>, Status> {
+        let (client, entitlements) = client_of(&request);
+        let remote_addr = request.remote_addr();
+        let alerts_request = request.into_inner();
+
+        self.check_symbol(&entitlements, "")?;
+        if let Some(exchange) = alerts_request
+            .exchanges
+            .iter()
+            .find(|exchange| !self.exchanges.contains(&exchange.as_str()))
+        {
+            return Err(Status::not_found(format!(
+                "Unknown exchange '{}'.",
+                exchange
+            )));
+        }
+        // The merged order-book's alerts are only included for every exchange.
+        let exchanges = entitlements.check_exchanges(&alerts_request.exchanges)?;
+        let is_included = move |alert: &proto::Alert| match &exchanges {
+            None => true,
+            Some(exchanges) => exchanges.contains(&alert.exchange),
+        };
+        let stream = self.open_stream(client, &entitlements, remote_addr)?;
+
+        // Subscribe before reading the firing alerts, so that no change is missed.
+        // The changes which were published meanwhile are also among the firing alerts,
+        // so only the alerts which change what was sent are sent.
+        let alerts = self.state.subscribe_alerts();
+        let mut sent_alerts = SentAlerts::default();
+        let mut is_change =
+            move |alert: &proto::Alert| is_included(alert) && sent_alerts.is_change(alert);
+        let initial = self
+            .state
+            .firing_alerts()
+            .into_iter()
+            .filter(&mut is_change)
+            .collect();
+        // A client which misses an alert would not know whether it is firing,
+        // so the stream is closed instead of skipping it.
+        Ok(Response::new(forward(
+            alerts,
+            initial,
+            100,
+            None,
+            Overflow::Close("The client is not keeping up with the alerts."),
+            stream,
+            move |alert| alert.filter(&mut is_change),
+        )))
+    }
+
+    async fn estimate_impact(
+        &self,
+        request: Request<proto::ImpactRequest>,
+    ) -> Result<Response<proto::MarketImpact>, Status> {
+        let (_, entitlements) = client_of(&request);
+        let impact_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &impact_request.symbol)?;
+        let summary = self.entitled_summary(&entitlements, &impact_request.exchanges)?;
+        let side = proto::Side::from_i32(impact_request.side)
+            .ok_or_else(|| Status::invalid_argument("Unknown side."))?;
+        if !(impact_request.quantity > 0.0 && impact_request.quantity.is_finite()) {
+            return Err(Status::invalid_argument("The quantity must be positive."));
+        }
+        let quantity = match proto::QuantityUnit::from_i32(impact_request.unit) {
+            Some(proto::QuantityUnit::Base) => Quantity::Base(impact_request.quantity),
+            Some(proto::QuantityUnit::Quote) => Quantity::Quote(impact_request.quantity),
+            None => return Err(Status::invalid_argument("Unknown quantity unit.")),
+        };
+
+        let impact = impact::estimate(&summary, side, quantity).ok_or_else(|| {
+            Status::failed_precondition("The merged order-book has no bids or no asks.")
+        })?;
+        Ok(Response::new(impact))
+    }
+
+    async fn simulate_route(
+        &self,
+        request: Request<proto::RouteRequest>,
+    ) -> Result<Response<proto::RoutingPlan>, Status> {
+        let (client, entitlements) = client_of(&request);
+        let route_request = request.into_inner();
+
+        self.check_symbol(&entitlements, &route_request.symbol)?;
+        let summary = self.entitled_summary(&entitlements, &route_request.exchanges)?;
+        let side = proto::Side::from_i32(route_request.side)
+            .ok_or_else(|| Status::invalid_argument("Unknown side."))?;
+        if !(route_request.quantity > 0.0 && route_request.quantity.is_finite()) {
+            return Err(Status::invalid_argument("The quantity must be positive."));
+        }
+        let limit_price = match route_request.limit_price {
+            0.0 => None,
+            price if price > 0.0 && price.is_finite() => Some(price),
+            _ => {
+                return Err(Status::invalid_argument(
+                    "The limit price must be positive.",
+                ))
+            }
+        };
+
+        let order = ParentOrder {
+            side,
+            quantity: route_request.quantity,
+            limit_price,
+        };
+        let plan = routing::route(&summary, &order, &self.exchange_terms);
+        info!(
+            "Client '{}' simulated routing {:?} into {} child orders.",
+            client,
+            order,
+            plan.child_orders.len()
+        );
+        Ok(Response::new(plan))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use tokio::sync::broadcast;
+    use tokio_stream::StreamExt;
+    use tonic::{Code, Request};
+
+    use super::{
+        client_name, connection_status, exchange_order_book, forward, merged_book_analytics,
+        ClientStream, OrderbookAggregatorService, Overflow,
+    };
+    use crate::common::fixtures::{level, order_book};
+    use crate::common::instrument::Instrument;
+    use crate::merger::MergedBook;
+    use crate::proto;
+    use crate::proto::orderbook_aggregator_server::OrderbookAggregator;
+    use crate::routing::ExchangeTerms;
+    use crate::rpc::limits::{StreamLimiter, StreamLimits};
+    use crate::state::{ConnectionStatus, MarketState, SequencedBook};
+
+    #[tokio::test]
+    async fn forward_changes_until_overflow() {
+        let state = Arc::new(MarketState::default());
+        state.client_connected("a", None);
+        let client_stream = ClientStream {
+            client: "a".to_string(),
+            remote_addr: None,
+            state: state.clone(),
+            _permit: StreamLimiter::default().acquire(None).unwrap(),
+        };
+        let (tx, rx) = broadcast::channel(10);
+        let mut stream = forward(
+            rx,
+            vec![1],
+            1,
+            None,
+            Overflow::Close("Too slow."),
+            client_stream,
+            |message: Option<i32>| message.filter(|message| *message != 3),
+        );
+        // The repeated message is not sent, and neither is the one left out.
+        tx.send(1).unwrap();
+        tx.send(3).unwrap();
+        // The client has not read the first message, so the stream is full.
+        tx.send(2).unwrap();
+        tokio::task::yield_now().await;
+
+        assert_eq!(1, stream.next().await.unwrap().unwrap());
+        let status = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(Code::ResourceExhausted, status.code());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[test]
+    fn exchange_book_with_status() {
+        let state = MarketState::default();
+        assert_eq!(
+            proto::ConnectionStatus::Connecting,
+            connection_status(state.exchange("binance").as_ref())
+        );
+        state.book_updated("binance");
+        assert_eq!(
+            proto::ConnectionStatus::Live,
+            connection_status(state.exchange("binance").as_ref())
+        );
+        state.set_status(
+            "binance",
+            ConnectionStatus::Disconnected,
+            "The stream failed.",
+            None,
+        );
+        let status = connection_status(state.exchange("binance").as_ref());
+        assert_eq!(proto::ConnectionStatus::Disconnected, status);
+
+        let book = SequencedBook {
+            sequence: 7,
+            order_book: order_book(
+                "binance",
+                &[(10.0, 1.0), (9.0, 1.0)],
+                &[(10.5, 1.0), (11.0, 1.0)],
+            ),
+        };
+        let message = exchange_order_book("binance", Some(&book), status, Some(1));
+        assert_eq!(7, message.sequence);
+        assert_eq!(proto::ConnectionStatus::Disconnected as i32, message.status);
+        assert_eq!(0.5, message.spread);
+        assert_eq!(vec![level("binance", 10.0, 1.0)], message.bids);
+
+        let message = exchange_order_book("binance", None, status, None);
+        assert_eq!(0, message.sequence);
+        assert!(message.bids.is_empty());
+    }
+
+    #[test]
+    fn name_the_client_by_its_token_and_certificate() {
+        assert_eq!("desk-a", client_name("desk-a", None));
+        assert_eq!("desk-a (trader-1)", client_name("desk-a", Some("trader-1")));
+    }
+
+    /// A merged order-book of exchange a with 15 bids of one each, 0.05 apart, and b's top of book.
+    fn deep_merged_book() -> MergedBook {
+        let bids: Vec<_> = (0..15).map(|i| (100.0 - 0.05 * i as f64, 1.0)).collect();
+        MergedBook {
+            sequence: 1,
+            timestamp_ns: 0,
+            summary: proto::Summary::default(),
+            order_books: Arc::new(HashMap::from([
+                ("a", order_book("a", &bids, &[(100.1, 1.0)])),
+                ("b", order_book("b", &[(99.0, 1.0)], &[(101.0, 1.0)])),
+            ])),
+        }
+    }
+
+    #[test]
+    fn analyze_every_level() {
+        let merged_book = deep_merged_book();
+        let depth_100_bps = |analytics: &proto::OrderBookAnalytics| analytics.depth[3].bid_amount;
+
+        // Every bid of a is within 100 bps of the mid, beyond the 10 levels of the merged order-book.
+        let analytics = merged_book_analytics(&merged_book, None, 5, usize::MAX);
+        let merged = analytics.merged.unwrap();
+        assert_eq!(100.0, merged.depth[3].bps);
+        assert_eq!(15.0, depth_100_bps(&merged));
+        assert_eq!(15.0, depth_100_bps(&analytics.exchanges[0]));
+
+        // The depth the client is entitled to limits the levels counted.
+        let analytics = merged_book_analytics(&merged_book, None, 5, 12);
+        assert_eq!(12.0, depth_100_bps(&analytics.merged.unwrap()));
+        assert_eq!(12.0, depth_100_bps(&analytics.exchanges[0]));
+    }
+
+    /// A service whose latest merged order-book is the given one.
+    fn service_of(
+        merged_book: MergedBook,
+        exchange_terms: HashMap<String, ExchangeTerms>,
+    ) -> OrderbookAggregatorService {
+        let state = Arc::new(MarketState::default());
+        state.set_latest(merged_book);
+        OrderbookAggregatorService::new(
+            Instrument::spot("ETH", "BTC"),
+            vec!["a", "b"],
+            broadcast::channel(10).0,
+            broadcast::channel(10).0,
+            state,
+            StreamLimits::default(),
+            exchange_terms,
+        )
+    }
+
+    #[tokio::test]
+    async fn estimate_impact_against_every_level() {
+        let impact = service_of(deep_merged_book(), HashMap::new())
+            .estimate_impact(Request::new(proto::ImpactRequest {
+                side: proto::Side::Sell as i32,
+                quantity: 15.0,
+                exchanges: vec!["a".to_string()],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        // a's 15 bids fill it, beyond the 10 levels of the merged order-book.
+        assert_eq!(
+            (15.0, 0.0),
+            (impact.base_quantity, impact.unfilled_quantity)
+        );
+        assert_eq!(100.0 - 0.05 * 14.0, impact.worst_price);
+    }
+
+    #[tokio::test]
+    async fn route_to_every_level() {
+        let mut merged_book = deep_merged_book();
+        Arc::make_mut(&mut merged_book.order_books)
+            .insert("b", order_book("b", &[(100.5, 1.0)], &[(101.0, 1.0)]));
+        let exchange_terms = HashMap::from([(
+            "b".to_string(),
+            ExchangeTerms {
+                min_order_size: 5.0,
+                ..Default::default()
+            },
+        )]);
+        let plan = service_of(merged_book, exchange_terms)
+            .simulate_route(Request::new(proto::RouteRequest {
+                side: proto::Side::Sell as i32,
+                quantity: 12.0,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        // b's best bid is less than its minimum order size,
+        // so it is routed to a's levels beyond the 10 levels of the merged order-book.
+        assert_eq!(
+            vec!["b"],
+            plan.skipped
+                .iter()
+                .map(|s| &*s.exchange)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, plan.child_orders.len());
+        assert_eq!(
+            ("a", 12.0),
+            (
+                &*plan.child_orders[0].exchange,
+                plan.child_orders[0].quantity
+            )
+        );
+        assert_eq!(0.0, plan.unrouted_quantity);
+    }
 }
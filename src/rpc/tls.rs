@@ -0,0 +1,76 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::Request;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// The identity of clients which did not present a certificate.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// TLS settings of the gRPC listener.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    /// PEM-encoded server certificate chain.
+    pub cert: PathBuf,
+    /// PEM-encoded server private key.
+    pub key: PathBuf,
+    /// PEM-encoded CA certificates to verify client certificates against.
+    /// Clients must present a certificate signed by one of them.
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn server_tls_config(&self) -> io::Result<ServerTlsConfig> {
+        let identity = Identity::from_pem(fs::read(&self.cert)?, fs::read(&self.key)?);
+        let mut tls = ServerTlsConfig::new().identity(identity);
+        if let Some(client_ca) = &self.client_ca {
+            tls = tls.client_ca_root(Certificate::from_pem(fs::read(client_ca)?));
+        }
+        Ok(tls)
+    }
+}
+
+/// The authenticated identity of the client:
+/// the common name of its certificate's subject, or the whole subject if it has none.
+pub fn client_identity<T>(request: &Request<T>) -> String {
+    peer_identity(request).unwrap_or_else(|| ANONYMOUS.to_string())
+}
+
+/// The identity of the client's certificate, if it presented one.
+pub fn peer_identity<T>(request: &Request<T>) -> Option<String> {
+    request.peer_certs().and_then(|certs| {
+        certs
+            .first()
+            .and_then(|cert| certificate_identity(cert.get_ref()))
+    })
+}
+
+fn certificate_identity(der: &[u8]) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(der).ok()?;
+    let subject = cert.subject();
+    let common_name = subject
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(|cn| cn.to_string());
+    Some(common_name.unwrap_or_else(|| subject.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use x509_parser::pem::parse_x509_pem;
+
+    use super::certificate_identity;
+
+    #[test]
+    fn identity_from_certificate() {
+        let pem = include_bytes!("../../tests/client_certificate.pem");
+        let (_, pem) = parse_x509_pem(pem).unwrap();
+        assert_eq!(
+            Some("desk-a".to_string()),
+            certificate_identity(&pem.contents)
+        );
+    }
+}
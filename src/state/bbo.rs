@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::proto;
+
+/// The number of best bid and offer changes kept.
+/// At a change every 10 ms it covers the last 15 minutes.
+const BBO_HISTORY_CAPACITY: usize = 100_000;
+
+/// The best bid and ask of the merged order-book from when they changed.
+#[derive(Clone, Debug, PartialEq)]
+struct BboSample {
+    at: Instant,
+    /// The best bid's exchange and price.  Unset if there are no bids.
+    bid: Option<(String, f64)>,
+    /// The best ask's exchange and price.  Unset if there are no asks.
+    ask: Option<(String, f64)>,
+}
+
+impl BboSample {
+    fn spread(&self) -> Option<f64> {
+        match (&self.bid, &self.ask) {
+            (Some((_, bid)), Some((_, ask))) => Some(ask - bid),
+            _ => None,
+        }
+    }
+}
+
+/// A ring buffer of the changes of the merged best bid and offer.
+#[derive(Debug)]
+pub struct BboHistory {
+    capacity: usize,
+    samples: VecDeque<BboSample>,
+}
+
+impl Default for BboHistory {
+    fn default() -> Self {
+        Self::new(BBO_HISTORY_CAPACITY)
+    }
+}
+
+impl BboHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record the best bid and offer, if they changed.
+    /// The oldest change is dropped once the history is full.
+    pub fn record(&mut self, at: Instant, top: &proto::TopOfBook) {
+        let level = |level: &Option<proto::Level>| {
+            level
+                .as_ref()
+                .map(|level| (level.exchange.clone(), level.price))
+        };
+        let sample = BboSample {
+            at,
+            bid: level(&top.bid),
+            ask: level(&top.ask),
+        };
+        if self
+            .samples
+            .back()
+            .is_some_and(|last| (&last.bid, &last.ask) == (&sample.bid, &sample.ask))
+        {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// The statistics of the best bid and offer over the window up to `now`.
+    /// Each change counts from when it happened until the next, or until `now`.
+    /// The whole history is used when the window is unset or longer.
+    /// Returns `None` if nothing was recorded in or before the window.
+    pub fn statistics(
+        &self,
+        now: Instant,
+        window: Option<Duration>,
+    ) -> Option<proto::SpreadStatistics> {
+        let first = self.samples.front()?.at;
+        let start = window
+            .and_then(|window| now.checked_sub(window))
+            .map_or(first, |start| start.max(first));
+        // The change in effect at the start of the window, and every one after it.
+        let skip = self
+            .samples
+            .iter()
+            .rposition(|sample| sample.at <= start)
+            .unwrap_or(0);
+
+        let mut samples = 0;
+        let mut spreads: Vec<f64> = vec![];
+        let mut spread_time = Duration::ZERO;
+        let mut weighted_spread = 0.0;
+        // `<exchange-name> => (<best-bid-time>, <best-ask-time>)`
+        let mut best_times: BTreeMap<&str, (Duration, Duration)> = BTreeMap::new();
+        let ends = self
+            .samples
+            .iter()
+            .skip(skip + 1)
+            .map(|sample| sample.at)
+            .chain([now]);
+        for (sample, end) in self.samples.iter().skip(skip).zip(ends) {
+            samples += 1;
+            let duration = end.saturating_duration_since(sample.at.max(start));
+            if let Some(spread) = sample.spread() {
+                spreads.push(spread);
+                spread_time += duration;
+                weighted_spread += spread * duration.as_secs_f64();
+            }
+            if let Some((exchange, _)) = &sample.bid {
+                best_times.entry(exchange).or_default().0 += duration;
+            }
+            if let Some((exchange, _)) = &sample.ask {
+                best_times.entry(exchange).or_default().1 += duration;
+            }
+        }
+
+        let window_time = now.saturating_duration_since(start);
+        let percent = |time: Duration| match window_time.as_secs_f64() {
+            0.0 => 0.0,
+            window_secs => time.as_secs_f64() / window_secs * 100.0,
+        };
+        let exchanges = best_times
+            .into_iter()
+            .map(|(exchange, (bid_time, ask_time))| proto::ExchangeShare {
+                exchange: exchange.to_string(),
+                best_bid_percent: percent(bid_time),
+                best_ask_percent: percent(ask_time),
+            })
+            .collect();
+        Some(proto::SpreadStatistics {
+            window_secs: window_time.as_secs_f64(),
+            samples,
+            min_spread: spreads.iter().copied().reduce(f64::min).unwrap_or(0.0),
+            max_spread: spreads.iter().copied().reduce(f64::max).unwrap_or(0.0),
+            mean_spread: match spreads.len() {
+                0 => 0.0,
+                count => spreads.iter().sum::<f64>() / count as f64,
+            },
+            time_weighted_spread: match spread_time.as_secs_f64() {
+                0.0 => 0.0,
+                spread_secs => weighted_spread / spread_secs,
+            },
+            exchanges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::BboHistory;
+    use crate::common::fixtures::level;
+    use crate::proto;
+
+    fn top(bid: (&str, f64), ask: (&str, f64)) -> proto::TopOfBook {
+        proto::TopOfBook {
+            bid: Some(level(bid.0, bid.1, 1.0)),
+            ask: Some(level(ask.0, ask.1, 1.0)),
+            spread: ask.1 - bid.1,
+        }
+    }
+
+    #[test]
+    fn record_changes() {
+        let t0 = Instant::now();
+        let secs = |secs| t0 + Duration::from_secs(secs);
+        let mut history = BboHistory::new(3);
+        history.record(secs(0), &top(("a", 10.0), ("b", 11.0)));
+        // Unchanged.
+        history.record(secs(1), &top(("a", 10.0), ("b", 11.0)));
+        history.record(secs(4), &top(("b", 10.5), ("b", 11.0)));
+        assert_eq!(2, history.samples.len());
+
+        let stats = history.statistics(secs(5), None).unwrap();
+        assert_eq!(5.0, stats.window_secs);
+        assert_eq!(2, stats.samples);
+        assert_eq!((0.5, 1.0), (stats.min_spread, stats.max_spread));
+        assert_eq!(0.75, stats.mean_spread);
+        assert_eq!((4.0 * 1.0 + 0.5) / 5.0, stats.time_weighted_spread);
+        let shares: Vec<_> = stats
+            .exchanges
+            .iter()
+            .map(|share| {
+                (
+                    share.exchange.as_str(),
+                    share.best_bid_percent,
+                    share.best_ask_percent,
+                )
+            })
+            .collect();
+        assert_eq!(vec![("a", 80.0, 0.0), ("b", 20.0, 100.0)], shares);
+
+        // The change in effect at the start of the window counts from the start.
+        let stats = history
+            .statistics(secs(5), Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(2, stats.samples);
+        assert_eq!((1.0 + 0.5) / 2.0, stats.time_weighted_spread);
+
+        // The oldest change is dropped.
+        history.record(secs(6), &top(("a", 10.0), ("b", 11.0)));
+        history.record(secs(7), &top(("a", 10.2), ("b", 11.0)));
+        assert_eq!(3, history.samples.len());
+        assert_eq!(secs(4), history.samples[0].at);
+
+        assert_eq!(None, BboHistory::default().statistics(secs(0), None));
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::warn;
+use tokio::sync::broadcast;
+
+use crate::common::OrderBook;
+use crate::merger::MergedBook;
+use crate::proto;
+
+mod bbo;
+
+use bbo::BboHistory;
+
+/// An exchange whose latest order-book is older than this is not live.
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// How often exchanges are checked for stale order-books.
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The state of an exchange's websocket connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connecting,
+    /// Connected and subscribed, but no order-book has been received yet.
+    Subscribed,
+    /// Connected and receiving order-books.
+    Live,
+    /// Connected, but no order-book has been received for [STALE_AFTER].
+    Stale,
+    /// The stream failed.
+    Disconnected,
+    /// Waiting to reconnect after the stream failed.
+    Reconnecting,
+    /// Waiting longer to reconnect after the stream failed repeatedly.
+    Quarantined,
+    /// Disabled by an operator.
+    Disabled,
+}
+
+impl ConnectionStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConnectionStatus::Connecting => "connecting",
+            ConnectionStatus::Subscribed => "subscribed",
+            ConnectionStatus::Live => "live",
+            ConnectionStatus::Stale => "stale",
+            ConnectionStatus::Disconnected => "disconnected",
+            ConnectionStatus::Reconnecting => "reconnecting",
+            ConnectionStatus::Quarantined => "quarantined",
+            ConnectionStatus::Disabled => "disabled",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExchangeState {
+    pub status: ConnectionStatus,
+    /// When the status last changed.
+    pub since: SystemTime,
+    /// Why the status last changed.
+    pub reason: String,
+    /// When the exchange's latest order-book was merged.
+    pub last_update: Option<Instant>,
+    pub last_error: Option<String>,
+    /// The number of order-books received from the exchange.
+    pub sequence: u64,
+}
+
+/// A change of an exchange's connection status.
+#[derive(Clone, Debug)]
+pub struct StatusEvent {
+    pub exchange: &'static str,
+    pub status: ConnectionStatus,
+    pub timestamp: SystemTime,
+    pub reason: String,
+    pub last_error: Option<String>,
+}
+
+impl ExchangeState {
+    fn new(status: ConnectionStatus, reason: String) -> Self {
+        Self {
+            status,
+            since: SystemTime::now(),
+            reason,
+            last_update: None,
+            last_error: None,
+            sequence: 0,
+        }
+    }
+
+    fn event(&self, exchange: &'static str) -> StatusEvent {
+        StatusEvent {
+            exchange,
+            status: self.status,
+            timestamp: self.since,
+            reason: self.reason.clone(),
+            last_error: self.last_error.clone(),
+        }
+    }
+
+    /// The age of the exchange's latest order-book.
+    pub fn book_age(&self) -> Option<Duration> {
+        self.last_update.map(|t| t.elapsed())
+    }
+
+    /// Receiving order-books, and the latest one is recent.
+    pub fn is_live(&self) -> bool {
+        self.status == ConnectionStatus::Live
+            && self.book_age().is_some_and(|age| age < STALE_AFTER)
+    }
+}
+
+/// An order-book of an exchange, numbered in the order they were received.
+#[derive(Clone, Debug)]
+pub struct SequencedBook {
+    pub sequence: u64,
+    pub order_book: OrderBook,
+}
+
+/// Stream counts of a gRPC client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientStreams {
+    pub active: u64,
+    pub total: u64,
+}
+
+/// The merged order-book and the exchanges' connection states,
+/// shared by the merger and the client-facing services.
+/// The latest merged order-book is set before it is published, so a stream which subscribes
+/// to the merged order-books and then starts with the latest one misses no update.
+pub struct MarketState {
+    latest: RwLock<Option<MergedBook>>,
+    /// The changes of the merged best bid and offer.
+    bbo_history: RwLock<BboHistory>,
+    /// `<exchange-name> => <exchange-state>`
+    exchanges: RwLock<BTreeMap<&'static str, ExchangeState>>,
+    /// `<exchange-name> => <latest-order-book>`
+    exchange_books: RwLock<BTreeMap<&'static str, SequencedBook>>,
+    /// `<client-identity> => <client-streams>`
+    clients: RwLock<BTreeMap<String, ClientStreams>>,
+    /// Exchange status changes are published on this broadcast channel.
+    status_tx: broadcast::Sender<StatusEvent>,
+    /// The alerts which are firing.
+    firing_alerts: RwLock<Vec<proto::Alert>>,
+    /// Alerts which fire and resolve are published on this broadcast channel.
+    alerts_tx: broadcast::Sender<proto::Alert>,
+}
+
+impl Default for MarketState {
+    fn default() -> Self {
+        Self {
+            latest: Default::default(),
+            bbo_history: Default::default(),
+            exchanges: Default::default(),
+            exchange_books: Default::default(),
+            clients: Default::default(),
+            status_tx: broadcast::channel(100).0,
+            firing_alerts: Default::default(),
+            alerts_tx: broadcast::channel(100).0,
+        }
+    }
+}
+
+impl MarketState {
+    /// The latest merged order-book, if one has been merged yet.
+    pub fn latest(&self) -> Option<MergedBook> {
+        self.latest.read().unwrap().clone()
+    }
+
+    /// Set the latest merged order-book, and record its best bid and offer if they changed.
+    pub fn set_latest(&self, merged_book: MergedBook) {
+        self.bbo_history
+            .write()
+            .unwrap()
+            .record(Instant::now(), &merged_book.summary.best_bid_offer());
+        *self.latest.write().unwrap() = Some(merged_book);
+    }
+
+    /// The statistics of the merged best bid and offer over the last `window`,
+    /// or over the whole history if unset.
+    pub fn spread_statistics(&self, window: Option<Duration>) -> Option<proto::SpreadStatistics> {
+        self.bbo_history
+            .read()
+            .unwrap()
+            .statistics(Instant::now(), window)
+    }
+
+    pub fn exchanges(&self) -> BTreeMap<&'static str, ExchangeState> {
+        self.exchanges.read().unwrap().clone()
+    }
+
+    pub fn exchange(&self, exchange: &str) -> Option<ExchangeState> {
+        self.exchanges.read().unwrap().get(exchange).cloned()
+    }
+
+    pub fn live_exchanges(&self) -> usize {
+        self.exchanges
+            .read()
+            .unwrap()
+            .values()
+            .filter(|state| state.is_live())
+            .count()
+    }
+
+    /// The current status of each exchange, as events.
+    pub fn status_events(&self) -> Vec<StatusEvent> {
+        self.exchanges
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(exchange, state)| state.event(exchange))
+            .collect()
+    }
+
+    /// Subscribe to the exchanges' status changes.
+    pub fn subscribe_status(&self) -> broadcast::Receiver<StatusEvent> {
+        self.status_tx.subscribe()
+    }
+
+    /// Change the exchange's status, and publish the change.
+    /// An error replaces the exchange's last error.
+    pub fn set_status(
+        &self,
+        exchange: &'static str,
+        status: ConnectionStatus,
+        reason: impl Into<String>,
+        error: Option<String>,
+    ) {
+        let mut exchanges = self.exchanges.write().unwrap();
+        let reason = reason.into();
+        let state = exchanges
+            .entry(exchange)
+            .or_insert_with(|| ExchangeState::new(status, reason.clone()));
+        state.status = status;
+        state.since = SystemTime::now();
+        state.reason = reason;
+        if error.is_some() {
+            state.last_error = error;
+        }
+        self.status_tx.send(state.event(exchange)).unwrap_or(0);
+    }
+
+    /// Record that an order-book was received from the exchange.
+    /// Returns the order-book's sequence number.
+    pub fn book_updated(&self, exchange: &'static str) -> u64 {
+        let mut exchanges = self.exchanges.write().unwrap();
+        // Exchanges without a reader, eg. replayed ones, are subscribed by their first order-book.
+        let state = exchanges
+            .entry(exchange)
+            .or_insert_with(|| ExchangeState::new(ConnectionStatus::Subscribed, String::new()));
+        state.last_update = Some(Instant::now());
+        state.sequence += 1;
+        // Order-books which were already on their way when the stream failed or was disabled
+        // do not make the exchange live again.
+        if matches!(
+            state.status,
+            ConnectionStatus::Subscribed | ConnectionStatus::Stale
+        ) {
+            state.reason = match state.status {
+                ConnectionStatus::Stale => "Order-books resumed.",
+                _ => "Receiving order-books.",
+            }
+            .to_string();
+            state.status = ConnectionStatus::Live;
+            state.since = SystemTime::now();
+            self.status_tx.send(state.event(exchange)).unwrap_or(0);
+        }
+        state.sequence
+    }
+
+    /// Mark live exchanges without a recent order-book as stale.
+    fn mark_stale(&self) {
+        let mut exchanges = self.exchanges.write().unwrap();
+        for (exchange, state) in exchanges.iter_mut() {
+            if state.status == ConnectionStatus::Live && !state.is_live() {
+                warn!("{} is stale.", exchange);
+                state.status = ConnectionStatus::Stale;
+                state.since = SystemTime::now();
+                state.reason = format!("No order-book for {} seconds.", STALE_AFTER.as_secs());
+                self.status_tx.send(state.event(exchange)).unwrap_or(0);
+            }
+        }
+    }
+
+    /// The latest order-book of the exchange, if one has been received yet.
+    pub fn exchange_book(&self, exchange: &str) -> Option<SequencedBook> {
+        self.exchange_books.read().unwrap().get(exchange).cloned()
+    }
+
+    pub fn set_exchange_book(&self, book: SequencedBook) {
+        self.exchange_books
+            .write()
+            .unwrap()
+            .insert(book.order_book.exchange, book);
+    }
+
+    /// Replace the exchange's latest order-book with an empty one numbered after it,
+    /// eg. when the exchange is disabled or its order-book is cleared.
+    /// Returns the empty order-book.
+    pub fn clear_exchange_book(&self, exchange: &'static str) -> SequencedBook {
+        let sequence = match self.exchanges.write().unwrap().get_mut(exchange) {
+            Some(state) => {
+                state.sequence += 1;
+                state.sequence
+            }
+            None => 0,
+        };
+        let book = SequencedBook {
+            sequence,
+            order_book: OrderBook::empty(exchange),
+        };
+        self.set_exchange_book(book.clone());
+        book
+    }
+
+    /// The alerts which are firing.
+    pub fn firing_alerts(&self) -> Vec<proto::Alert> {
+        self.firing_alerts.read().unwrap().clone()
+    }
+
+    /// Subscribe to the alerts which fire and resolve.
+    pub fn subscribe_alerts(&self) -> broadcast::Receiver<proto::Alert> {
+        self.alerts_tx.subscribe()
+    }
+
+    /// Publish the alerts which fired and resolved, and replace the firing alerts.
+    pub fn publish_alerts(&self, changes: Vec<proto::Alert>, firing: Vec<proto::Alert>) {
+        *self.firing_alerts.write().unwrap() = firing;
+        for alert in changes {
+            self.alerts_tx.send(alert).unwrap_or(0);
+        }
+    }
+
+    pub fn clients(&self) -> BTreeMap<String, ClientStreams> {
+        self.clients.read().unwrap().clone()
+    }
+
+    /// Count a new stream of the client, unless it already has `max_streams`.
+    /// Returns whether the stream was accepted.
+    pub fn client_connected(&self, client: &str, max_streams: Option<u64>) -> bool {
+        let mut clients = self.clients.write().unwrap();
+        let streams = clients.entry(client.to_string()).or_default();
+        if max_streams.is_some_and(|max| streams.active >= max) {
+            return false;
+        }
+        streams.active += 1;
+        streams.total += 1;
+        true
+    }
+
+    pub fn client_disconnected(&self, client: &str) {
+        if let Some(streams) = self.clients.write().unwrap().get_mut(client) {
+            streams.active = streams.active.saturating_sub(1);
+        }
+    }
+}
+
+/// Periodically mark live exchanges without a recent order-book as stale.
+pub async fn detect_stale_exchanges(state: Arc<MarketState>) {
+    let mut interval = tokio::time::interval(STALE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        state.mark_stale();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionStatus, MarketState};
+
+    #[test]
+    fn count_live_exchanges() {
+        let state = MarketState::default();
+        state.set_status("binance", ConnectionStatus::Subscribed, "Subscribed.", None);
+        state.book_updated("bitstamp");
+        assert_eq!(1, state.live_exchanges());
+
+        state.book_updated("binance");
+        assert_eq!(2, state.live_exchanges());
+
+        state.set_status(
+            "bitstamp",
+            ConnectionStatus::Disconnected,
+            "The stream failed.",
+            Some("The stream closed.".to_string()),
+        );
+        assert_eq!(1, state.live_exchanges());
+        let bitstamp = &state.exchanges()["bitstamp"];
+        assert_eq!(Some("The stream closed."), bitstamp.last_error.as_deref());
+        assert!(bitstamp.last_update.is_some());
+        assert_eq!(1, bitstamp.sequence);
+    }
+
+    #[test]
+    fn ignore_queued_books_after_failure() {
+        let state = MarketState::default();
+        state.set_status("binance", ConnectionStatus::Subscribed, "Subscribed.", None);
+        state.book_updated("binance");
+        state.set_status(
+            "binance",
+            ConnectionStatus::Quarantined,
+            "The stream failed repeatedly.",
+            None,
+        );
+        // An order-book which was queued before the stream failed.
+        assert_eq!(2, state.book_updated("binance"));
+        assert_eq!(
+            ConnectionStatus::Quarantined,
+            state.exchanges()["binance"].status
+        );
+        assert_eq!(0, state.live_exchanges());
+
+        state.set_status("binance", ConnectionStatus::Subscribed, "Subscribed.", None);
+        state.book_updated("binance");
+        assert_eq!(1, state.live_exchanges());
+    }
+
+    #[test]
+    fn publish_status_changes() {
+        let state = MarketState::default();
+        let mut events = state.subscribe_status();
+        state.set_status("binance", ConnectionStatus::Subscribed, "Subscribed.", None);
+        state.book_updated("binance");
+        // Only the first order-book changes the status.
+        state.book_updated("binance");
+        state.set_status(
+            "binance",
+            ConnectionStatus::Disconnected,
+            "The stream failed.",
+            Some("Connection reset.".to_string()),
+        );
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(ConnectionStatus::Subscribed, event.status);
+        assert_eq!("Subscribed.", event.reason);
+        assert_eq!(ConnectionStatus::Live, events.try_recv().unwrap().status);
+        let event = events.try_recv().unwrap();
+        assert_eq!(ConnectionStatus::Disconnected, event.status);
+        assert_eq!(Some("Connection reset."), event.last_error.as_deref());
+        assert!(events.try_recv().is_err());
+
+        let current = state.status_events();
+        assert_eq!(1, current.len());
+        assert_eq!("The stream failed.", current[0].reason);
+    }
+
+    #[test]
+    fn limit_client_streams() {
+        let state = MarketState::default();
+        assert!(state.client_connected("desk-a", Some(1)));
+        assert!(!state.client_connected("desk-a", Some(1)));
+        assert!(state.client_connected("desk-b", None));
+
+        state.client_disconnected("desk-a");
+        assert!(state.client_connected("desk-a", Some(1)));
+        assert_eq!(2, state.clients()["desk-a"].total);
+    }
+}
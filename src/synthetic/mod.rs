@@ -0,0 +1,321 @@
+use std::fmt;
+use std::sync::Arc;
+
+use log::error;
+use tokio::sync::mpsc;
+
+use crate::common::instrument::Instrument;
+use crate::common::OrderBook;
+use crate::exchange::metadata::InstrumentInfo;
+use crate::exchange::{Exchange, ExchangeError};
+use crate::proto;
+use crate::recorder::FrameRecorder;
+use crate::state::{ConnectionStatus, MarketState};
+
+/// An instrument which an exchange does not list, priced by trading through
+/// a common currency on two instruments which it does,
+/// eg. ETH/BTC from ETH/USDT and BTC/USDT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntheticInstrument {
+    pub instrument: Instrument,
+    /// The first leg trades the base currency against the common currency,
+    /// and the second the common currency against the quote currency.
+    pub legs: [Instrument; 2],
+    /// Whether each leg is quoted the other way round, eg. USDT/ETH rather than ETH/USDT.
+    inverted: [bool; 2],
+}
+
+impl SyntheticInstrument {
+    /// Find the common currency of the legs, which may be given in either order.
+    pub fn new(instrument: Instrument, legs: [Instrument; 2]) -> Result<Self, String> {
+        let trades =
+            |leg: &Instrument, currency: &str| leg.base == currency || leg.quote == currency;
+        let other = |leg: &Instrument, currency: &str| {
+            if leg.base == currency {
+                leg.quote.clone()
+            } else {
+                leg.base.clone()
+            }
+        };
+        let [a, b] = legs;
+        let (first, second) = if trades(&a, &instrument.base) {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let invalid = || {
+            Err(format!(
+                "{} can not be built from {} and {}.",
+                instrument, first, second
+            ))
+        };
+        if !trades(&first, &instrument.base) || !trades(&second, &instrument.quote) {
+            return invalid();
+        }
+        let common = other(&first, &instrument.base);
+        if common == instrument.quote
+            || common == instrument.base
+            || other(&second, &instrument.quote) != common
+        {
+            return invalid();
+        }
+
+        Ok(Self {
+            inverted: [first.base != instrument.base, second.base != common],
+            legs: [first, second],
+            instrument,
+        })
+    }
+
+    /// Combine the legs' order-books into the synthetic instrument's order-book.
+    /// Each synthetic ask buys the base currency with the common currency on the first leg,
+    /// and buys that common currency with the quote currency on the second leg,
+    /// walking both legs' levels, so that its amount can be taken at its price.
+    /// Bids sell the same way.
+    /// The levels are attributed to the first leg's exchange,
+    /// and the order-book is received when the later of the legs was.
+    pub fn combine(&self, first: &OrderBook, second: &OrderBook) -> OrderBook {
+        let (first_bids, first_asks) = oriented(first, self.inverted[0]);
+        let (second_bids, second_asks) = oriented(second, self.inverted[1]);
+        let level = |(price, amount)| proto::Level {
+            exchange: first.exchange.to_string(),
+            price,
+            amount,
+            ..Default::default()
+        };
+        OrderBook {
+            exchange: first.exchange,
+            bids: walk(&first_bids, &second_bids)
+                .into_iter()
+                .map(level)
+                .collect(),
+            asks: walk(&first_asks, &second_asks)
+                .into_iter()
+                .map(level)
+                .collect(),
+            received_ns: first.received_ns.max(second.received_ns),
+        }
+    }
+}
+
+impl fmt::Display for SyntheticInstrument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} from {} and {}",
+            self.instrument, self.legs[0], self.legs[1]
+        )
+    }
+}
+
+/// `(<price>, <amount>)` levels of one side of an order-book, best price first.
+type Levels = Vec<(f64, f64)>;
+
+/// The `(<price>, <amount>)` bids and asks of a leg, quoted the way round the synthetic
+/// instrument trades it.  Inverting a leg swaps its sides, and turns its prices into
+/// their reciprocals and its amounts into the quote currency.
+fn oriented(order_book: &OrderBook, inverted: bool) -> (Levels, Levels) {
+    let levels = |levels: &[proto::Level]| -> Levels {
+        levels
+            .iter()
+            .filter(|level| level.price > 0.0 && level.amount > 0.0)
+            .map(|level| {
+                if inverted {
+                    (1.0 / level.price, level.amount * level.price)
+                } else {
+                    (level.price, level.amount)
+                }
+            })
+            .collect()
+    };
+    if inverted {
+        (levels(&order_book.asks), levels(&order_book.bids))
+    } else {
+        (levels(&order_book.bids), levels(&order_book.asks))
+    }
+}
+
+/// Walk the same side of both legs, best price first.
+/// Each level of the first leg is an amount of the base currency priced in the common currency,
+/// and each level of the second leg an amount of the common currency priced in the quote currency.
+/// A synthetic level is produced for each span where neither leg's level changes.
+fn walk(first: &[(f64, f64)], second: &[(f64, f64)]) -> Levels {
+    let mut levels = vec![];
+    let mut first_levels = first.iter().copied();
+    let mut second_levels = second.iter().copied();
+    let (mut a, mut b) = match (first_levels.next(), second_levels.next()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return levels,
+    };
+    loop {
+        // The second leg's remaining amount, in the base currency.
+        let second_amount = b.1 / a.0;
+        let amount = a.1.min(second_amount);
+        levels.push((a.0 * b.0, amount));
+
+        // Exhaust whichever level is smaller without subtracting, so that no dust remains.
+        let first_exhausted = a.1 <= second_amount;
+        let second_exhausted = second_amount <= a.1;
+        if !first_exhausted {
+            a.1 -= amount;
+        }
+        if !second_exhausted {
+            b.1 -= amount * a.0;
+        }
+        if first_exhausted {
+            match first_levels.next() {
+                Some(next) => a = next,
+                None => break,
+            }
+        }
+        if second_exhausted {
+            match second_levels.next() {
+                Some(next) => b = next,
+                None => break,
+            }
+        }
+    }
+    levels
+}
+
+/// The latest order-books of a synthetic instrument's legs.
+pub struct SyntheticBook {
+    instrument: Arc<SyntheticInstrument>,
+    legs: [Option<OrderBook>; 2],
+}
+
+impl SyntheticBook {
+    pub fn new(instrument: Arc<SyntheticInstrument>) -> Self {
+        Self {
+            instrument,
+            legs: [None, None],
+        }
+    }
+
+    /// Update the order-book of the leg at `index`.
+    /// Returns the synthetic order-book once both legs have one.
+    pub fn update(&mut self, index: usize, order_book: OrderBook) -> Option<OrderBook> {
+        self.legs[index] = Some(order_book);
+        match &self.legs {
+            [Some(first), Some(second)] => Some(self.instrument.combine(first, second)),
+            _ => None,
+        }
+    }
+}
+
+/// Connect and read from both legs' websocket streams, and send the synthetic order-book
+/// whenever either leg's order-book changes.
+/// The exchange is subscribed once both legs are.
+/// Returns when either leg's stream does.
+pub async fn start(
+    exchange: &dyn Exchange,
+    instrument: Arc<SyntheticInstrument>,
+    legs: &[InstrumentInfo; 2],
+    recorder: Option<&FrameRecorder>,
+    state: &MarketState,
+    sink: mpsc::Sender<OrderBook>,
+) -> Result<(), ExchangeError> {
+    let (first_stream, second_stream) = tokio::try_join!(
+        exchange.connect(&legs[0].symbol),
+        exchange.connect(&legs[1].symbol)
+    )?;
+    state.set_status(
+        exchange.name(),
+        ConnectionStatus::Subscribed,
+        format!(
+            "Subscribed to '{}' and '{}'.",
+            legs[0].symbol, legs[1].symbol
+        ),
+        None,
+    );
+
+    let (first_tx, mut first_rx) = mpsc::channel(100);
+    let (second_tx, mut second_rx) = mpsc::channel(100);
+    let first = exchange.read(first_stream, &legs[0], recorder, first_tx);
+    let second = exchange.read(second_stream, &legs[1], recorder, second_tx);
+    tokio::pin!(first, second);
+
+    let mut synthetic_book = SyntheticBook::new(instrument);
+    loop {
+        let order_book = tokio::select! {
+            result = &mut first => return result,
+            result = &mut second => return result,
+            Some(order_book) = first_rx.recv() => synthetic_book.update(0, order_book),
+            Some(order_book) = second_rx.recv() => synthetic_book.update(1, order_book),
+        };
+        if let Some(order_book) = order_book {
+            if let Err(err) = sink.send(order_book).await {
+                error!("Error sending order book message on the channel:  {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyntheticInstrument;
+    use crate::common::fixtures::order_book;
+    use crate::common::instrument::Instrument;
+    use crate::proto;
+
+    fn assert_levels(expected: &[(f64, f64)], levels: &[proto::Level]) {
+        assert_eq!(expected.len(), levels.len(), "{:?}", levels);
+        for (&(price, amount), level) in expected.iter().zip(levels) {
+            assert!((level.price - price).abs() < 1e-12, "{:?}", levels);
+            assert!((level.amount - amount).abs() < 1e-9, "{:?}", levels);
+        }
+    }
+
+    #[test]
+    fn combine_leg_depth() {
+        let eth_btc = Instrument::spot("ETH", "BTC");
+        let eth_usdt = Instrument::spot("ETH", "USDT");
+        let btc_usdt = Instrument::spot("BTC", "USDT");
+        // The legs may be given in either order.
+        let synthetic =
+            SyntheticInstrument::new(eth_btc.clone(), [btc_usdt.clone(), eth_usdt.clone()])
+                .unwrap();
+        assert_eq!([eth_usdt.clone(), btc_usdt.clone()], synthetic.legs);
+
+        let eth_usdt_book = order_book("a", &[(2000.0, 1.0), (1990.0, 4.0)], &[(2010.0, 2.0)]);
+        let mut btc_usdt_book =
+            order_book("a", &[(40000.0, 0.1)], &[(40100.0, 0.025), (40200.0, 1.0)]);
+        btc_usdt_book.received_ns = 2;
+        let eth_btc_book = synthetic.combine(&eth_usdt_book, &btc_usdt_book);
+        assert_eq!(("a", 2), (eth_btc_book.exchange, eth_btc_book.received_ns));
+        // Selling ETH for USDT buys BTC with it:  the 1002.5 USDT of the best BTC ask
+        // take 0.50125 ETH of the best ETH bid, and the rest of it buys at the next ask.
+        assert_levels(
+            &[
+                (2000.0 / 40100.0, 0.50125),
+                (2000.0 / 40200.0, 0.49875),
+                (1990.0 / 40200.0, 4.0),
+            ],
+            &eth_btc_book.bids,
+        );
+        // Selling the 0.1 BTC of the only BTC bid buys 4000 USDT of ETH.
+        assert_levels(&[(2010.0 / 40000.0, 4000.0 / 2010.0)], &eth_btc_book.asks);
+
+        // BTC/ETH from the same legs trades the ETH/USDT leg the other way round.
+        let btc_eth = SyntheticInstrument::new(
+            Instrument::spot("BTC", "ETH"),
+            [eth_usdt.clone(), btc_usdt.clone()],
+        )
+        .unwrap();
+        let btc_eth_book = btc_eth.combine(&btc_usdt_book, &eth_usdt_book);
+        assert_levels(
+            &[
+                (40100.0 / 2000.0, 0.025),
+                (40200.0 / 2000.0, 997.5 / 40200.0),
+                (40200.0 / 1990.0, 7960.0 / 40200.0),
+            ],
+            &btc_eth_book.asks,
+        );
+
+        // The legs must share a currency other than the synthetic instrument's.
+        assert!(SyntheticInstrument::new(eth_btc.clone(), [eth_usdt.clone(), eth_btc]).is_err());
+        assert!(
+            SyntheticInstrument::new(Instrument::spot("ETH", "EUR"), [eth_usdt, btc_usdt]).is_err()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::SocketAddr;
+
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::common::instrument::Instrument;
+use crate::merger::MergedBook;
+use crate::proto;
+
+/// Requests from websocket clients, eg.
+/// `{"action": "subscribe", "symbol": "ETH/BTC", "depth": 5}`
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ClientMessage {
+    Subscribe {
+        symbol: String,
+        /// The number of levels of each side.  All levels if not given.
+        depth: Option<usize>,
+    },
+    Unsubscribe {
+        symbol: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum ServerMessage<'a> {
+    Subscribed {
+        symbol: String,
+        depth: Option<usize>,
+    },
+    Unsubscribed {
+        symbol: String,
+    },
+    Book {
+        symbol: String,
+        #[serde(flatten)]
+        summary: &'a proto::Summary,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Publishes the merged order-books as JSON to websocket clients.
+pub struct WebsocketServer {
+    symbol: Instrument,
+    /// Subscribe to this broadcast channel for the merged order-book stream.
+    broadcast_tx: broadcast::Sender<MergedBook>,
+}
+
+/// A client's subscription to the merged order-book.
+struct Subscription {
+    depth: Option<usize>,
+}
+
+impl WebsocketServer {
+    pub fn new(symbol: Instrument, channel: broadcast::Sender<MergedBook>) -> Self {
+        Self {
+            symbol,
+            broadcast_tx: channel,
+        }
+    }
+
+    /// Accept websocket connections until the listener fails.
+    pub async fn serve(self, addr: SocketAddr) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let symbol = self.symbol.clone();
+            let merged_order_books = self.broadcast_tx.subscribe();
+            tokio::spawn(async move {
+                info!("Websocket client connected from {}", peer);
+                if let Err(err) = handle_connection(stream, symbol, merged_order_books).await {
+                    error!("Websocket connection from {} failed:  {}", peer, err);
+                }
+                info!("Websocket client disconnected from {}", peer);
+            });
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    symbol: Instrument,
+    mut merged_order_books: broadcast::Receiver<MergedBook>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let mut ws = tokio_tungstenite::accept_async(stream).await?;
+    let mut subscription: Option<Subscription> = None;
+
+    loop {
+        tokio::select! {
+            message = ws.next() => {
+                let text = match message {
+                    None | Some(Ok(Message::Close(_))) => break,
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => return Err(err),
+                };
+                debug!("Websocket message received:  {}", text);
+                let response = handle_client_message(&text, &symbol, &mut subscription);
+                ws.send(Message::Text(serde_json::to_string(&response).unwrap())).await?;
+            }
+            summary = merged_order_books.recv() => {
+                let summary = match summary {
+                    Ok(merged_book) => merged_book.summary,
+                    // Skip the order-books which this client was too slow to receive.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if let Some(Subscription { depth }) = &subscription {
+                    let summary = match depth {
+                        Some(depth) => summary.with_depth(*depth),
+                        None => summary,
+                    };
+                    let book = ServerMessage::Book {
+                        symbol: symbol.to_string(),
+                        summary: &summary,
+                    };
+                    ws.send(Message::Text(serde_json::to_string(&book).unwrap())).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Update the client's subscription and return the response to the client.
+fn handle_client_message<'a>(
+    text: &str,
+    symbol: &Instrument,
+    subscription: &mut Option<Subscription>,
+) -> ServerMessage<'a> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(err) => {
+            return ServerMessage::Error {
+                message: format!("Invalid request:  {}", err),
+            }
+        }
+    };
+    let requested = match &message {
+        ClientMessage::Subscribe { symbol, .. } | ClientMessage::Unsubscribe { symbol } => symbol,
+    };
+    if requested.parse::<Instrument>().as_ref() != Ok(symbol) {
+        return ServerMessage::Error {
+            message: format!("Unknown symbol '{}'.", requested),
+        };
+    }
+
+    match message {
+        ClientMessage::Subscribe { depth, .. } => {
+            *subscription = Some(Subscription { depth });
+            ServerMessage::Subscribed {
+                symbol: symbol.to_string(),
+                depth,
+            }
+        }
+        ClientMessage::Unsubscribe { .. } => {
+            *subscription = None;
+            ServerMessage::Unsubscribed {
+                symbol: symbol.to_string(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_client_message, ServerMessage, Subscription};
+    use crate::common::fixtures::level;
+    use crate::common::instrument::Instrument;
+    use crate::proto;
+
+    #[test]
+    fn subscribe_and_unsubscribe() {
+        let symbol = Instrument::spot("ETH", "BTC");
+        let mut subscription: Option<Subscription> = None;
+
+        let response = handle_client_message(
+            r#"{"action": "subscribe", "symbol": "ethbtc", "depth": 5}"#,
+            &symbol,
+            &mut subscription,
+        );
+        assert!(matches!(
+            response,
+            ServerMessage::Subscribed { depth: Some(5), .. }
+        ));
+        assert_eq!(Some(5), subscription.as_ref().unwrap().depth);
+
+        let response = handle_client_message(
+            r#"{"action": "subscribe", "symbol": "ETH/USDT"}"#,
+            &symbol,
+            &mut subscription,
+        );
+        assert!(matches!(response, ServerMessage::Error { .. }));
+
+        let response = handle_client_message(
+            r#"{"action": "unsubscribe", "symbol": "ETH/BTC"}"#,
+            &symbol,
+            &mut subscription,
+        );
+        assert!(matches!(response, ServerMessage::Unsubscribed { .. }));
+        assert!(subscription.is_none());
+    }
+
+    #[test]
+    fn serialize_book() {
+        let summary = proto::Summary {
+            spread: 0.5,
+            bids: vec![level("binance", 10.0, 1.0)],
+            asks: vec![],
+        };
+        let book = ServerMessage::Book {
+            symbol: "ETH/BTC".to_string(),
+            summary: &summary,
+        };
+        assert_eq!(
+            r#"{"event":"book","symbol":"ETH/BTC","spread":0.5,"bids":[{"exchange":"binance","price":10.0,"amount":1.0}],"asks":[]}"#,
+            serde_json::to_string(&book).unwrap()
+        );
+    }
+}